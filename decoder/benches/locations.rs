@@ -0,0 +1,48 @@
+//! Measures `Table::get_locations` on a synthetic compilation unit with many log statements that
+//! are all declared in the same source file.
+//!
+//! Run with `cargo bench -p defmt-decoder --features unstable`.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use defmt_decoder::{StringEntry, Table, TableEntry, Tag};
+
+// the benchmark only needs some of the builders
+#[allow(dead_code)]
+#[path = "../src/elf2table/test_elf.rs"]
+mod test_elf;
+
+use test_elf::{elf_with_units, TestUnit};
+
+const LOG_STATEMENTS: usize = 1_000;
+const RUNS: u32 = 100;
+
+fn main() {
+    let mut unit = TestUnit::new();
+    let mut entries = BTreeMap::new();
+    for i in 0..LOG_STATEMENTS {
+        let symbol = format!(
+            r#"{{"package":"app","tag":"defmt_info","data":"message {}","disambiguator":"{}"}}"#,
+            i, i
+        );
+        unit.add_log_statement(unit.namespace, &symbol, i as u64, i as u64 + 1);
+        let string = StringEntry::new(Tag::Info, format!("message {}", i));
+        entries.insert(i, TableEntry::new(string, symbol));
+    }
+    let elf = elf_with_units(vec![unit]);
+    let table = Table::new(entries);
+
+    // warm up, and check that every log statement is found
+    let locations = table.get_locations(&elf).unwrap();
+    assert_eq!(locations.len(), LOG_STATEMENTS);
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        table.get_locations(&elf).unwrap();
+    }
+    println!(
+        "get_locations: {} log statements, 1 file: {:?} per run",
+        LOG_STATEMENTS,
+        start.elapsed() / RUNS
+    );
+}
@@ -0,0 +1,74 @@
+//! Measures the throughput of `FrameStreamDecoder` on a stream of large frames that is fed in
+//! small chunks, like a serial port delivers it, with
+//!
+//! - one decoder for the whole stream, which is pushed each chunk, and
+//! - a new decoder per chunk, which the undecoded bytes are copied into and back out of (what
+//!   callers that can't keep a decoder do, like `defmt_print::decode_frames`).
+//!
+//! Run with `cargo bench -p defmt-decoder --features unstable`.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use defmt_decoder::{FrameStreamDecoder, StringEntry, Table, TableEntry, Tag};
+
+const FRAMES: usize = 20_000;
+const PAYLOAD_LEN: usize = 1_000;
+/// Size of the chunks the stream is fed in; `defmt_print::READ_BUFFER_SIZE`
+const CHUNK_SIZE: usize = 1024;
+
+fn main() {
+    let mut entries = BTreeMap::new();
+    entries.insert(
+        0,
+        TableEntry::new(
+            StringEntry::new(Tag::Info, "x={=[u8]}".to_owned()),
+            "<bench>".to_owned(),
+        ),
+    );
+    let table = Table::new(entries);
+
+    let mut frame = vec![0]; // index
+    let mut len = PAYLOAD_LEN;
+    while len >= 0x80 {
+        frame.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    frame.push(len as u8);
+    frame.extend((0..PAYLOAD_LEN).map(|i| i as u8));
+    let stream = frame.repeat(FRAMES);
+
+    let start = Instant::now();
+    let mut decoder = table.new_stream_decoder();
+    let mut decoded = 0;
+    for chunk in stream.chunks(CHUNK_SIZE) {
+        decoder.push(chunk);
+        decoded += decoder.frames().map(Result::unwrap).count();
+    }
+    report("one decoder", start, decoded, stream.len());
+
+    let start = Instant::now();
+    let mut leftover = Vec::new();
+    let mut decoded = 0;
+    for chunk in stream.chunks(CHUNK_SIZE) {
+        leftover.extend_from_slice(chunk);
+        let mut decoder = FrameStreamDecoder::new(&table);
+        decoder.push(&leftover);
+        decoded += decoder.frames().map(Result::unwrap).count();
+        leftover = decoder.buffered().to_vec();
+    }
+    report("decoder per chunk", start, decoded, stream.len());
+}
+
+fn report(name: &str, start: Instant, decoded: usize, bytes: usize) {
+    let elapsed = start.elapsed();
+    assert_eq!(decoded, FRAMES);
+    println!(
+        "{}: {} frames of {} bytes in {} byte chunks: {:?} ({:.0} MB/s)",
+        name,
+        FRAMES,
+        PAYLOAD_LEN,
+        CHUNK_SIZE,
+        elapsed,
+        bytes as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
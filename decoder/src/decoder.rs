@@ -24,6 +24,8 @@ enum FormatList<'t> {
 pub(crate) struct Decoder<'t, 'b> {
     table: &'t Table,
     pub bytes: &'b [u8],
+    /// Length of the whole frame; used to report the offset of malformed data
+    frame_len: usize,
     format_list: Option<FormatList<'t>>,
     // below an enum tags must be included
     below_enum: bool,
@@ -33,16 +35,34 @@ pub(crate) struct Decoder<'t, 'b> {
 const MAX_NUM_BOOL_FLAGS: usize = 8;
 
 impl<'t, 'b> Decoder<'t, 'b> {
-    pub fn new(table: &'t Table, bytes: &'b [u8]) -> Self {
+    pub fn new(table: &'t Table, bytes: &'b [u8], frame_len: usize) -> Self {
         Self {
             table,
             bytes,
+            frame_len,
             format_list: None,
             bools_tbd: Vec::new(),
             below_enum: false,
         }
     }
 
+    /// Offset of the next unread byte, relative to the start of the frame
+    fn offset(&self) -> usize {
+        self.frame_len - self.bytes.len()
+    }
+
+    fn malformed_at(&self, offset: usize, context: &'static str) -> DecodeError {
+        DecodeError::Malformed { offset, context }
+    }
+
+    fn read_leb128(&mut self) -> Result<u64, DecodeError> {
+        let offset = self.offset();
+        read_leb128(&mut self.bytes).map_err(|e| match e {
+            DecodeError::Malformed { context, .. } => self.malformed_at(offset, context),
+            e => e,
+        })
+    }
+
     /// Reads a byte of packed bools and unpacks them into `args` at the given indices.
     pub fn read_and_unpack_bools(&mut self) -> Result<(), DecodeError> {
         let bool_flags = self.bytes.read_u8()?;
@@ -84,11 +104,12 @@ impl<'t, 'b> Decoder<'t, 'b> {
             }
         }
 
-        let index = read_leb128(&mut self.bytes)?;
+        let offset = self.offset();
+        let index = self.read_leb128()?;
         let format = self
             .table
             .get_without_level(index as usize)
-            .map_err(|_| DecodeError::Malformed)?;
+            .map_err(|_| self.malformed_at(offset, "unknown format string index"))?;
 
         if let Some(FormatList::Build { formats }) = self.format_list.as_mut() {
             if !self.below_enum {
@@ -103,6 +124,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
         // NOTE nesting of enums, like "A|B(C|D)" is not possible; indirection is
         // required: "A|B({:?})" where "{:?}" -> "C|D"
         let num_variants = format.chars().filter(|c| *c == '|').count();
+        let offset = self.offset();
 
         let discriminant: usize = if u8::try_from(num_variants).is_ok() {
             self.bytes.read_u8()?.into()
@@ -112,20 +134,20 @@ impl<'t, 'b> Decoder<'t, 'b> {
             self.bytes
                 .read_u32::<LE>()?
                 .try_into()
-                .map_err(|_| DecodeError::Malformed)?
+                .map_err(|_| self.malformed_at(offset, "enum discriminant out of range"))?
         } else if u64::try_from(num_variants).is_ok() {
             self.bytes
                 .read_u64::<LE>()?
                 .try_into()
-                .map_err(|_| DecodeError::Malformed)?
+                .map_err(|_| self.malformed_at(offset, "enum discriminant out of range"))?
         } else {
-            return Err(DecodeError::Malformed);
+            return Err(self.malformed_at(offset, "too many enum variants"));
         };
 
         format
             .split('|')
             .nth(discriminant)
-            .ok_or(DecodeError::Malformed)
+            .ok_or_else(|| self.malformed_at(offset, "enum discriminant out of range"))
     }
 
     fn decode_format_slice(
@@ -213,7 +235,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
     pub fn decode_format(&mut self, format: &str) -> Result<Vec<Arg<'t>>, DecodeError> {
         let mut args = vec![]; // will contain the deserialized arguments on return
         let mut params = defmt_parser::parse(format, defmt_parser::ParserMode::ForwardsCompatible)
-            .map_err(|_| DecodeError::Malformed)?
+            .map_err(|_| self.malformed_at(self.offset(), "invalid format string"))?
             .iter()
             .filter_map(|frag| match frag {
                 Fragment::Parameter(param) => Some(param.clone()),
@@ -224,6 +246,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
         self.prepare_params(&mut params);
 
         for param in &params {
+            let offset = self.offset();
             match &param.ty {
                 Type::I8 => args.push(Arg::Ixx(self.bytes.read_i8()? as i128)),
                 Type::I16 => args.push(Arg::Ixx(self.bytes.read_i16::<LE>()? as i128)),
@@ -231,9 +254,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                 Type::I64 => args.push(Arg::Ixx(self.bytes.read_i64::<LE>()? as i128)),
                 Type::I128 => args.push(Arg::Ixx(self.bytes.read_i128::<LE>()?)),
                 // Signed isize is encoded in zigzag-encoding.
-                Type::Isize => args.push(Arg::Ixx(
-                    zigzag_decode(read_leb128(&mut self.bytes)?) as i128
-                )),
+                Type::Isize => args.push(Arg::Ixx(zigzag_decode(self.read_leb128()?) as i128)),
                 Type::U8 => args.push(Arg::Uxx(self.bytes.read_u8()? as u128)),
                 Type::U16 => args.push(Arg::Uxx(self.bytes.read_u16::<LE>()? as u128)),
                 Type::U24 => {
@@ -245,7 +266,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                 Type::U32 => args.push(Arg::Uxx(self.bytes.read_u32::<LE>()? as u128)),
                 Type::U64 => args.push(Arg::Uxx(self.bytes.read_u64::<LE>()? as u128)),
                 Type::U128 => args.push(Arg::Uxx(self.bytes.read_u128::<LE>()? as u128)),
-                Type::Usize => args.push(Arg::Uxx(read_leb128(&mut self.bytes)? as u128)),
+                Type::Usize => args.push(Arg::Uxx(self.read_leb128()? as u128)),
                 Type::F32 => args.push(Arg::F32(f32::from_bits(self.bytes.read_u32::<LE>()?))),
                 Type::F64 => args.push(Arg::F64(f64::from_bits(self.bytes.read_u64::<LE>()?))),
                 Type::Bool => {
@@ -258,7 +279,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     }
                 }
                 Type::FormatSlice => {
-                    let num_elements = read_leb128(&mut self.bytes)? as usize;
+                    let num_elements = self.read_leb128()? as usize;
                     let elements = self.decode_format_slice(num_elements)?;
                     args.push(Arg::FormatSlice { elements });
                 }
@@ -305,7 +326,7 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     args.push(Arg::Uxx(data));
                 }
                 Type::Str => {
-                    let str_len = read_leb128(&mut self.bytes)? as usize;
+                    let str_len = self.read_leb128()? as usize;
                     let mut arg_str_bytes = vec![];
 
                     // note: went for the suboptimal but simple solution; optimize if necessary
@@ -314,24 +335,24 @@ impl<'t, 'b> Decoder<'t, 'b> {
                     }
 
                     // convert to utf8 (no copy)
-                    let arg_str =
-                        String::from_utf8(arg_str_bytes).map_err(|_| DecodeError::Malformed)?;
+                    let arg_str = String::from_utf8(arg_str_bytes)
+                        .map_err(|_| self.malformed_at(offset, "invalid UTF-8 in `str`"))?;
 
                     args.push(Arg::Str(arg_str));
                 }
                 Type::IStr => {
-                    let str_index = read_leb128(&mut self.bytes)? as usize;
+                    let str_index = self.read_leb128()? as usize;
 
                     let string = self
                         .table
                         .get_without_level(str_index as usize)
-                        .map_err(|_| DecodeError::Malformed)?;
+                        .map_err(|_| self.malformed_at(offset, "unknown interned string index"))?;
 
                     args.push(Arg::IStr(string));
                 }
                 Type::U8Slice => {
                     // only supports byte slices
-                    let num_elements = read_leb128(&mut self.bytes)? as usize;
+                    let num_elements = self.read_leb128()? as usize;
                     let mut arg_slice = vec![];
 
                     // note: went for the suboptimal but simple solution; optimize if necessary
@@ -354,7 +375,8 @@ impl<'t, 'b> Decoder<'t, 'b> {
                 }
                 Type::Char => {
                     let data = self.bytes.read_u32::<LE>()?;
-                    let c = std::char::from_u32(data).ok_or(DecodeError::Malformed)?;
+                    let c = std::char::from_u32(data)
+                        .ok_or_else(|| self.malformed_at(offset, "invalid `char`"))?;
                     args.push(Arg::Char(c));
                 }
                 Type::Debug | Type::Display => {
@@ -365,8 +387,9 @@ impl<'t, 'b> Decoder<'t, 'b> {
                         .iter()
                         .position(|b| *b == 0xff)
                         .ok_or(DecodeError::UnexpectedEof)?;
-                    let data = core::str::from_utf8(&self.bytes[..end])
-                        .map_err(|_| DecodeError::Malformed)?;
+                    let data = core::str::from_utf8(&self.bytes[..end]).map_err(|_| {
+                        self.malformed_at(offset, "invalid UTF-8 in preformatted string")
+                    })?;
                     self.bytes = &self.bytes[end + 1..];
 
                     args.push(Arg::Preformatted(data.into()));
@@ -437,7 +460,10 @@ fn merge_bitfields(params: &mut Vec<Parameter>) {
 pub fn read_leb128(bytes: &mut &[u8]) -> Result<u64, DecodeError> {
     match leb128::read::unsigned(bytes) {
         Ok(val) => Ok(val),
-        Err(leb128::read::Error::Overflow) => Err(DecodeError::Malformed),
+        Err(leb128::read::Error::Overflow) => Err(DecodeError::Malformed {
+            offset: 0,
+            context: "LEB128 integer overflow",
+        }),
         Err(leb128::read::Error::IoError(io)) => Err(io.into()),
     }
 }
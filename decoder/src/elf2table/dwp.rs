@@ -0,0 +1,128 @@
+//! Unit index of packed split DWARF (`.dwp`) files.
+//!
+//! A `.dwp` file holds the split units of all object files of a program, each in its own slice
+//! (contribution) of the `.debug_*.dwo` sections. The `.debug_cu_index` section says where these
+//! slices are; see DWARF 5 section 7.3.5. The GNU extension to DWARF 4 uses the same layout with
+//! version number 2 and different section identifiers.
+
+use std::ops::Range;
+
+use anyhow::ensure;
+use gimli::{Reader, SectionId};
+
+/// The parsed `.debug_cu_index` section of a `.dwp` file
+pub struct UnitIndex {
+    version: u32,
+    /// The `DW_SECT_*` identifier of each column of `offsets` and `sizes`
+    sections: Vec<u32>,
+    /// The hash table: the signature (DWO ID) of each slot ...
+    signatures: Vec<u64>,
+    /// ... and its row in `offsets` and `sizes`, plus 1; 0 for an empty slot
+    rows: Vec<u32>,
+    /// Start of each contribution, by row, then column
+    offsets: Vec<u32>,
+    /// Size of each contribution, by row, then column
+    sizes: Vec<u32>,
+}
+
+impl UnitIndex {
+    pub fn parse<R: Reader>(mut data: R) -> Result<Self, anyhow::Error> {
+        // DWARF 5 has a 2-byte version and 2 bytes of padding; the GNU extension a 4-byte version
+        let mut header = data.clone();
+        let version = match data.read_u32()? {
+            2 => 2,
+            _ => u32::from(header.read_u16()?),
+        };
+        ensure!(
+            version == 2 || version == 5,
+            "unsupported unit index version {}",
+            version
+        );
+
+        let columns = data.read_u32()? as usize;
+        let units = data.read_u32()? as usize;
+        let slots = data.read_u32()? as usize;
+        ensure!(
+            slots == 0 || slots.is_power_of_two(),
+            "the number of hash table slots ({}) is not a power of 2",
+            slots
+        );
+
+        let signatures = (0..slots)
+            .map(|_| data.read_u64())
+            .collect::<Result<Vec<_>, _>>()?;
+        let rows = (0..slots)
+            .map(|_| data.read_u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        ensure!(
+            rows.iter().all(|row| *row as usize <= units),
+            "the hash table refers to a unit that is not in the index"
+        );
+        let mut read_u32s = |n| {
+            (0..n)
+                .map(|_| data.read_u32())
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let sections = read_u32s(columns)?;
+        let offsets = read_u32s(units * columns)?;
+        let sizes = read_u32s(units * columns)?;
+
+        Ok(Self {
+            version,
+            sections,
+            signatures,
+            rows,
+            offsets,
+            sizes,
+        })
+    }
+
+    /// Returns the row of the unit whose DWO ID is `dwo_id`, if it is in the index
+    pub fn find(&self, dwo_id: u64) -> Option<usize> {
+        if self.signatures.is_empty() {
+            return None;
+        }
+
+        let mask = self.signatures.len() as u64 - 1;
+        let step = ((dwo_id >> 32) & mask) | 1;
+        let mut slot = dwo_id & mask;
+        for _ in 0..self.signatures.len() {
+            let row = self.rows[slot as usize];
+            if row == 0 {
+                return None;
+            }
+            if self.signatures[slot as usize] == dwo_id {
+                return Some(row as usize - 1);
+            }
+            slot = (slot + step) & mask;
+        }
+
+        None
+    }
+
+    /// Returns the byte range of the contribution of the unit in `row` to `section`
+    ///
+    /// Returns `None` for sections that the units share, like `.debug_str.dwo`.
+    pub fn contribution(&self, row: usize, section: SectionId) -> Option<Range<usize>> {
+        let id = self.section_id(section)?;
+        let column = self.sections.iter().position(|column| *column == id)?;
+        let i = row * self.sections.len() + column;
+        let start = self.offsets[i] as usize;
+        Some(start..start + self.sizes[i] as usize)
+    }
+
+    /// Returns the `DW_SECT_*` identifier of `section`
+    fn section_id(&self, section: SectionId) -> Option<u32> {
+        Some(match (self.version, section) {
+            (_, SectionId::DebugInfo) => 1,
+            (2, SectionId::DebugTypes) => 2,
+            (_, SectionId::DebugAbbrev) => 3,
+            (_, SectionId::DebugLine) => 4,
+            (2, SectionId::DebugLoc) | (5, SectionId::DebugLocLists) => 5,
+            (_, SectionId::DebugStrOffsets) => 6,
+            (2, SectionId::DebugMacinfo) | (5, SectionId::DebugMacro) => 7,
+            (2, SectionId::DebugMacro) | (5, SectionId::DebugRngLists) => 8,
+            _ => return None,
+        })
+    }
+}
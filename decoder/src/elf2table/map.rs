@@ -0,0 +1,156 @@
+//! Best-effort recovery of [`Location`]s from a linker map file.
+//!
+//! This is a fallback for firmware that was built without debug info: the map file doesn't contain
+//! file or line information, but it does tell us which object file each defmt symbol came from.
+
+use std::path::PathBuf;
+
+use super::{symbol::Symbol, Location, Locations};
+use crate::Table;
+
+/// Builds a (degraded) [`Locations`] map from the contents of an `ld` or `lld` map file.
+///
+/// * `file` is always empty and `line` is always `0`, as map files contain no source information
+/// * `object` is set to the object file that contained the log statement (or `None` if the map
+///   file doesn't say)
+/// * `module` is set to the name of the package (crate) that contains the log statement
+///
+/// Symbols that are not part of `table` (e.g. because they were GC-ed by the linker) are skipped.
+pub fn get_locations_from_map(map: &str, table: &Table) -> Locations {
+    let mut locations = Locations::new();
+    let mut object = None;
+
+    for line in map.lines() {
+        let (prefix, symbol) = match line.find("{\"") {
+            Some(start) => line.split_at(start),
+            None => (line, ""),
+        };
+
+        let mut tokens = prefix.split_whitespace();
+        let address = tokens.next().and_then(parse_hex);
+        let is_symbol_line = !symbol.is_empty() && tokens.all(|token| parse_hex(token).is_some());
+
+        if !is_symbol_line {
+            if let Some(path) = input_object(prefix) {
+                object = Some(path);
+            }
+            continue;
+        }
+
+        let (address, symbol) = match (address, symbol.trim_end()) {
+            (Some(address), symbol) if table.raw_symbols().any(|raw| raw == symbol) => {
+                (address, symbol)
+            }
+            _ => continue,
+        };
+
+        let package = match Symbol::demangle(symbol) {
+            Ok(sym) => sym.package().to_string(),
+            Err(_) => continue,
+        };
+
+        locations.entry(address).or_insert_with(|| Location {
+            index: address,
+            file: PathBuf::new(),
+            line: 0,
+            column: None,
+            module: package,
+            crate_name: None,
+            crate_version: None,
+            object: object.map(PathBuf::from),
+        });
+    }
+
+    locations
+}
+
+/// Extracts the object file path from an input section line.
+///
+/// `lld` prints `<path>:(<section>)`; GNU `ld` prints `<address> <size> <path>`.
+fn input_object(line: &str) -> Option<&str> {
+    if let Some(end) = line.find(":(") {
+        return line[..end].split_whitespace().last();
+    }
+
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    match tokens[tokens.len().saturating_sub(3)..] {
+        [address, size, path] if address.starts_with("0x") && size.starts_with("0x") => Some(path),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{StringEntry, TableEntry, Tag};
+
+    const INFO: &str =
+        r#"{"package":"app","tag":"defmt_info","data":"Hello, world!","disambiguator":"1"}"#;
+    const WARN: &str = r#"{"package":"drv","tag":"defmt_warn","data":"uh oh","disambiguator":"2"}"#;
+
+    fn table() -> Table {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            1,
+            TableEntry::new(
+                StringEntry::new(Tag::Info, "Hello, world!".into()),
+                INFO.into(),
+            ),
+        );
+        entries.insert(
+            2,
+            TableEntry::new(StringEntry::new(Tag::Warn, "uh oh".into()), WARN.into()),
+        );
+        Table::new(entries)
+    }
+
+    #[test]
+    fn lld() {
+        let map = format!(
+            "             VMA              LMA     Size Align Out     In      Symbol
+               0                0        3     1 .defmt
+               1                1        1     1         /tmp/libapp-1234.rlib(app-1234.app.0.rcgu.o):(.defmt.{})
+               1                1        0     1                 {}
+               2                2        1     1         /tmp/libdrv-5678.rlib(drv-5678.drv.0.rcgu.o):(.defmt.{})
+               2                2        0     1                 {}
+",
+            INFO, INFO, WARN, WARN
+        );
+
+        let locs = get_locations_from_map(&map, &table());
+        assert_eq!(locs.len(), 2);
+        assert_eq!(
+            locs[&1].object,
+            Some(PathBuf::from(
+                "/tmp/libapp-1234.rlib(app-1234.app.0.rcgu.o)"
+            ))
+        );
+        assert_eq!(locs[&1].file, PathBuf::new());
+        assert_eq!(locs[&1].module, "app");
+        assert_eq!(locs[&2].module, "drv");
+    }
+
+    #[test]
+    fn gnu_ld() {
+        let map = format!(
+            " .defmt.{}
+                0x0000000000000001        0x1 /tmp/libapp-1234.rlib(app.o)
+                0x0000000000000001                {}
+ .defmt.3       0x0000000000000003        0x1 /tmp/libgc.rlib(gc.o)
+                0x0000000000000003                {{\"package\":\"gc\",\"tag\":\"defmt_info\",\"data\":\"gone\",\"disambiguator\":\"3\"}}
+",
+            INFO, INFO
+        );
+
+        let locs = get_locations_from_map(&map, &table());
+        assert_eq!(locs.len(), 1);
+        assert_eq!(locs[&1].line, 0);
+        assert_eq!(format!("{:?}", locs[&1]), "/tmp/libapp-1234.rlib(app.o)");
+    }
+}
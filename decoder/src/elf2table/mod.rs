@@ -3,23 +3,147 @@
 //! This is an implementation detail of [`probe-run`](https://github.com/knurling-rs/probe-run) and
 //! not meant to be consumed by other tools at the moment so all the API is unstable.
 
+mod dwp;
+mod map;
 mod symbol;
+mod table_file;
+#[cfg(test)]
+mod test_elf;
 
 use std::{
     borrow::Cow,
-    collections::BTreeMap,
-    fmt,
-    path::{Path, PathBuf},
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    fmt, fs, io,
+    ops::Deref,
+    path::{Component, Path, PathBuf},
 };
 
+pub use map::get_locations_from_map;
+pub use table_file::{read_table_file, write_table_file};
+
 use crate::{StringEntry, Table, TableEntry, Tag, DEFMT_VERSION};
 use anyhow::{anyhow, bail, ensure};
 use object::{Object, ObjectSection, ObjectSymbol};
+use serde::{Deserialize, Serialize};
+
+/// Name of the output section that `defmt.x` places all defmt symbols in
+pub const DEFAULT_SECTION_NAME: &str = ".defmt";
+
+/// Error returned by [`Table::parse`] and its variants
+#[derive(Debug)]
+pub enum ParseError {
+    /// The data is not an object file that can be read
+    ObjectParse(object::Error),
+    /// The ELF file contains `_defmt_version_` symbols for more than one version
+    MultipleVersions { found: String, new: String },
+    /// The ELF file contains a `_defmt_version_` symbol, but no defmt section
+    MissingSection { section: String },
+    /// The ELF file contains a defmt section, but no `_defmt_version_` symbol
+    MissingVersionSymbol { section: String },
+    /// The firmware uses a defmt version that this decoder does not support
+    VersionMismatch {
+        found: String,
+        /// Explanation including a suggestion on how to fix the mismatch
+        message: String,
+    },
+    /// The ELF file is for a big-endian target
+    BigEndian,
+    /// A symbol in the defmt section is not a valid defmt symbol
+    MalformedSymbol {
+        symbol: String,
+        source: serde_json::Error,
+    },
+    /// The ELF file defines more than one timestamp format
+    MultipleTimestamps,
+    /// The contents of a section extend past the end of the file, e.g. because the file was read
+    /// while the linker was still writing it
+    Truncated {
+        section: String,
+        end: u64,
+        file_len: u64,
+    },
+    /// Two different defmt symbols have the same address (and therefore the same table index),
+    /// e.g. in two parts of a partitioned defmt section
+    DuplicateAddress {
+        address: usize,
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::ObjectParse(e) => write!(f, "failed to parse the ELF file: {}", e),
+            ParseError::MultipleVersions { found, new } => write!(
+                f,
+                "multiple defmt versions in use: {} and {} (only one is supported)",
+                found, new
+            ),
+            ParseError::MissingSection { section } => write!(
+                f,
+                "defmt version found, but no `{}` section - check your linker configuration",
+                section
+            ),
+            ParseError::MissingVersionSymbol { section } => write!(
+                f,
+                "`{}` section found, but no version symbol - check your linker configuration",
+                section
+            ),
+            ParseError::VersionMismatch { message, .. } => f.write_str(message),
+            ParseError::BigEndian => f.write_str(
+                "big-endian targets are not supported (defmt's wire format is little-endian)",
+            ),
+            ParseError::MalformedSymbol { symbol, source } => {
+                write!(f, "malformed defmt symbol `{}`: {}", symbol, source)
+            }
+            ParseError::MultipleTimestamps => {
+                f.write_str("multiple timestamp format specifications found")
+            }
+            ParseError::Truncated {
+                section,
+                end,
+                file_len,
+            } => write!(
+                f,
+                "the ELF file appears to be truncated: section `{}` ends at byte {} but the file \
+                 is only {} bytes long (was it read while still being written?)",
+                section, end, file_len
+            ),
+            ParseError::DuplicateAddress {
+                address,
+                first,
+                second,
+            } => write!(
+                f,
+                "defmt symbols `{}` and `{}` have the same address 0x{:08x}",
+                first, second, address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::ObjectParse(e) => Some(e),
+            ParseError::MalformedSymbol { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse_impl(
+    elf: &[u8],
+    check_version: bool,
+    section_name: &str,
+) -> Result<Option<Table>, ParseError> {
+    let file_len = elf.len() as u64;
+    let elf = object::File::parse(elf).map_err(ParseError::ObjectParse)?;
+    check_truncation(&elf, file_len)?;
 
-pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyhow::Error> {
-    let elf = object::File::parse(elf)?;
     // first pass to extract the `_defmt_version`
-    let mut version = None;
+    let mut version: Option<&str> = None;
     let is_defmt_version = |name: &str| {
         name.starts_with("\"_defmt_version_ = ") || name.starts_with("_defmt_version_ = ")
     };
@@ -39,11 +163,10 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
                 .trim_start_matches("_defmt_version_ = ")
                 .trim_end_matches('"');
             if let Some(version) = version {
-                return Err(anyhow!(
-                    "multiple defmt versions in use: {} and {} (only one is supported)",
-                    version,
-                    new_version
-                ));
+                return Err(ParseError::MultipleVersions {
+                    found: version.to_string(),
+                    new: new_version.to_string(),
+                });
             }
             version = Some(new_version);
         }
@@ -52,23 +175,42 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
     // NOTE: We need to make sure to return `Ok(None)`, not `Err`, when defmt is not in use.
     // Otherwise probe-run won't work with apps that don't use defmt.
 
-    let defmt_shndx = elf.section_by_name(".defmt").map(|s| s.index());
+    // LTO or section partitioning may split the section into `.defmt.*` parts
+    let defmt_shndxs = elf
+        .sections()
+        .filter(|section| {
+            section
+                .name()
+                .is_ok_and(|name| is_defmt_section(name, section_name))
+        })
+        .map(|section| section.index())
+        .collect::<Vec<_>>();
 
-    let (defmt_shndx, version) = match (defmt_shndx, version) {
-        (None, None) => return Ok(None), // defmt is not used
-        (Some(defmt_shndx), Some(version)) => (defmt_shndx, version),
-        (None, Some(_)) => {
-            bail!("defmt version found, but no `.defmt` section - check your linker configuration");
+    let version = match (defmt_shndxs.is_empty(), version) {
+        (true, None) => return Ok(None), // defmt is not used
+        (false, Some(version)) => version,
+        (true, Some(_)) => {
+            return Err(ParseError::MissingSection {
+                section: section_name.to_string(),
+            });
         }
-        (Some(_), None) => {
-            bail!(
-                "`.defmt` section found, but no version symbol - check your linker configuration"
-            );
+        (false, None) => {
+            return Err(ParseError::MissingVersionSymbol {
+                section: section_name.to_string(),
+            });
         }
     };
 
     if check_version {
-        self::check_version(version).map_err(anyhow::Error::msg)?;
+        self::check_version(version).map_err(|message| ParseError::VersionMismatch {
+            found: version.to_string(),
+            message,
+        })?;
+    }
+
+    // the decoder assumes little-endian data everywhere; fail loudly instead of printing garbage
+    if !elf.is_little_endian() {
+        return Err(ParseError::BigEndian);
     }
 
     // second pass to demangle symbols
@@ -89,13 +231,20 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
             continue;
         }
 
-        if entry.section_index() == Some(defmt_shndx) {
-            let sym = symbol::Symbol::demangle(name)?;
+        if entry
+            .section_index()
+            .is_some_and(|index| defmt_shndxs.contains(&index))
+        {
+            let sym =
+                symbol::Symbol::demangle(name).map_err(|source| ParseError::MalformedSymbol {
+                    symbol: name.to_string(),
+                    source,
+                })?;
             match sym.tag() {
                 symbol::SymbolTag::Defmt(tag) => {
                     if tag == Tag::Timestamp {
                         if timestamp.is_some() {
-                            bail!("multiple timestamp format specifications found");
+                            return Err(ParseError::MultipleTimestamps);
                         }
 
                         timestamp = Some(TableEntry::new(
@@ -103,13 +252,27 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
                             name.to_string(),
                         ));
                     } else {
-                        map.insert(
-                            entry.address() as usize,
-                            TableEntry::new(
-                                StringEntry::new(tag, sym.data().to_string()),
-                                name.to_string(),
-                            ),
+                        let address = entry.address() as usize;
+                        let entry = TableEntry::new(
+                            StringEntry::new(tag, sym.data().to_string()),
+                            name.to_string(),
                         );
+                        match map.entry(address) {
+                            Entry::Vacant(vacant) => {
+                                vacant.insert(entry);
+                            }
+                            // the same symbol may be listed more than once; that's harmless
+                            Entry::Occupied(occupied)
+                                if occupied.get().raw_symbol != entry.raw_symbol =>
+                            {
+                                return Err(ParseError::DuplicateAddress {
+                                    address,
+                                    first: occupied.get().raw_symbol.clone(),
+                                    second: entry.raw_symbol,
+                                });
+                            }
+                            Entry::Occupied(_) => {}
+                        }
                     }
                 }
                 symbol::SymbolTag::Custom(_) => {}
@@ -121,9 +284,112 @@ pub fn parse_impl(elf: &[u8], check_version: bool) -> Result<Option<Table>, anyh
     if let Some(ts) = timestamp {
         table.set_timestamp_entry(ts);
     }
+    table.set_version(version.to_string());
     Ok(Some(table))
 }
 
+/// Checks that the contents of all sections lie within the file
+///
+/// `object` only checks the section header table; without this a truncated file looks like one
+/// that lacks defmt symbols.
+fn check_truncation(elf: &object::File, file_len: u64) -> Result<(), ParseError> {
+    for section in elf.sections() {
+        if let Some((offset, size)) = section.file_range() {
+            let end = offset.saturating_add(size);
+            if end > file_len {
+                return Err(ParseError::Truncated {
+                    section: section.name().unwrap_or("<unnamed>").to_string(),
+                    end,
+                    file_len,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if `name` is the defmt section `section_name` (e.g. `.defmt`) or a part of it
+/// (e.g. `.defmt.1`)
+fn is_defmt_section(name: &str, section_name: &str) -> bool {
+    match name.strip_prefix(section_name) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Returns the defmt symbols of the ELF file, keyed by their address (= table index)
+///
+/// These are the raw (JSON encoded) symbols that [`Table::parse`] builds its entries from, i.e.
+/// what [`Table::raw_symbol`] returns for each index; the version, marker and timestamp symbols
+/// are not included. Symbols that were GC-ed by the linker are not part of the symbol table and
+/// therefore don't appear either, so comparing the map against a linker map tells which log
+/// statements survived.
+///
+/// Unlike [`Table::parse`], this doesn't check whether this decoder supports the defmt version of
+/// the ELF file.
+///
+/// Returns an empty map if defmt is not in use.
+pub fn symbol_map(elf: &[u8]) -> Result<BTreeMap<usize, String>, anyhow::Error> {
+    Ok(match parse_impl(elf, false, DEFAULT_SECTION_NAME)? {
+        Some(table) => table
+            .entries
+            .into_iter()
+            .map(|(address, entry)| (address, entry.raw_symbol))
+            .collect(),
+        None => BTreeMap::new(),
+    })
+}
+
+/// Returns the names of all sections in the ELF file, in the order they appear in the section
+/// header table.
+///
+/// This is meant for diagnostics, e.g. when [`Table::parse`] returns `None` because there's no
+/// `.defmt` section (usually a sign that the defmt linker script is missing).
+pub fn section_names(elf: &[u8]) -> Result<Vec<String>, anyhow::Error> {
+    let elf = object::File::parse(elf)?;
+    Ok(elf
+        .sections()
+        .filter_map(|section| section.name().ok().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
+/// Returns `true` if firmware that uses defmt `version` can be decoded by this version of the
+/// `decoder` crate.
+///
+/// Versions are compared by their compatibility key, which is also how `DEFMT_VERSION` is
+/// written: `major.minor` for `0.x` versions (every minor bump is breaking before 1.0), `major`
+/// for later versions (patch and minor bumps, pre-release and build metadata are ignored), and
+/// the whole string for git revisions (which only match themselves). So `0.2` is compatible with
+/// `0.2.3`, but not with `0.3`; `1` is compatible with `1.4.0`, but not with `2`.
+pub fn is_compatible_version(version: &str) -> bool {
+    versions_compatible(version, DEFMT_VERSION)
+}
+
+fn versions_compatible(a: &str, b: &str) -> bool {
+    compatibility_key(a) == compatibility_key(b)
+}
+
+fn compatibility_key(version: &str) -> Cow<'_, str> {
+    // a leading number; ignores pre-release and build metadata like in `1-alpha` or `2+build`
+    let number = |part: &str| {
+        let end = part
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(part.len());
+        part[..end].parse::<u64>().ok()
+    };
+
+    let mut parts = version.split('.');
+    let major = parts.next().and_then(number);
+    let minor = parts.next().and_then(number);
+    match (major, minor) {
+        (Some(0), Some(minor)) => Cow::Owned(format!("0.{}", minor)),
+        (Some(major), _) if major != 0 => Cow::Owned(major.to_string()),
+        // git revision
+        _ => Cow::Borrowed(version),
+    }
+}
+
 /// Checks if the version encoded in the symbol table is compatible with this version of the `decoder` crate
 fn check_version(version: &str) -> Result<(), String> {
     enum Kind {
@@ -143,7 +409,7 @@ fn check_version(version: &str) -> Result<(), String> {
         }
     }
 
-    if version != DEFMT_VERSION {
+    if !is_compatible_version(version) {
         let mut msg = format!(
             "defmt version mismatch: firmware is using {}, `probe-run` supports {}\nsuggestion: ",
             version, DEFMT_VERSION
@@ -178,24 +444,153 @@ panic-probe = {{ git = \"https://github.com/knurling-rs/defmt\", features = [\"p
     Ok(())
 }
 
+/// The contents of an ELF file, as returned by [`read_elf`]
+pub struct ElfBytes(ElfBytesInner);
+
+enum ElfBytesInner {
+    #[cfg(feature = "memmap2")]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for ElfBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            #[cfg(feature = "memmap2")]
+            ElfBytesInner::Mapped(mmap) => mmap,
+            ElfBytesInner::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Reads the ELF file at `path`
+///
+/// With the `memmap2` feature the file is memory-mapped instead of read into memory, which keeps
+/// peak memory usage down when working with large (debug) ELF images. The file must not be
+/// modified while the returned [`ElfBytes`] is alive.
+pub fn read_elf(path: &Path) -> io::Result<ElfBytes> {
+    #[cfg(feature = "memmap2")]
+    {
+        let file = fs::File::open(path)?;
+        // empty files can't be mapped
+        if file.metadata()?.len() != 0 {
+            // SAFETY: see the "must not be modified" requirement in the documentation above
+            let mmap = unsafe { memmap2::Mmap::map(&file)? };
+            return Ok(ElfBytes(ElfBytesInner::Mapped(mmap)));
+        }
+    }
+
+    fs::read(path).map(|bytes| ElfBytes(ElfBytesInner::Owned(bytes)))
+}
+
 /// Location of a defmt log statement in the elf-file
-#[derive(Clone)]
+///
+/// NOTE bump [`SERDE_FORMAT_VERSION`](crate::SERDE_FORMAT_VERSION) when changing the fields.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Location {
+    /// The index of the log statement; also its key in [`Locations`]
+    #[serde(default)]
+    pub index: u64,
+    /// Source file of the log statement; empty if unknown
     pub file: PathBuf,
+    /// `0` if unknown
     pub line: u64,
+    /// `None` if the firmware's debug info doesn't include column information
+    pub column: Option<u64>,
     pub module: String,
+    /// Name of the crates.io crate the log statement is in
+    ///
+    /// Derived from the Cargo registry path of `file`; `None` for local (path) crates, like the
+    /// firmware itself, and for git dependencies.
+    #[serde(default)]
+    pub crate_name: Option<String>,
+    /// Version of the crate in `crate_name`
+    #[serde(default)]
+    pub crate_version: Option<String>,
+    /// Object file (e.g. `libapp.rlib(app.o)`) that the log statement was linked from
+    ///
+    /// Only set for the approximate locations of [`Table::get_locations_from_map`], which have an
+    /// empty `file` and a `line` of `0`.
+    #[serde(default)]
+    pub object: Option<PathBuf>,
 }
 
 impl fmt::Debug for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.file.display(), self.line)
+        match &self.object {
+            Some(object) if self.line == 0 => write!(f, "{}", object.display()),
+            _ => write!(f, "{}:{}", self.file.display(), self.line),
+        }
     }
 }
 
 /// Mapping of memory address to [`Location`]
 pub type Locations = BTreeMap<u64, Location>;
 
+/// Two log statements whose debug info claims the same address
+///
+/// This indicates a bug in the DWARF variable filter (or in the debug info itself). Only the
+/// first location is kept in the [`Locations`] map. Both locations have the same `index`.
+#[derive(Clone, Debug)]
+pub struct LocationCollision {
+    pub kept: Location,
+    pub discarded: Location,
+}
+
+impl fmt::Display for LocationCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "index collision for addr 0x{:08x} (kept = {:?}, discarded = {:?})",
+            self.kept.index, self.kept, self.discarded
+        )
+    }
+}
+
+/// Options for [`Table::get_locations_with`] and [`Table::location_resolver`]
+#[derive(Clone, Debug, Default)]
+pub struct LocationsOptions {
+    /// `(from, to)` path prefix substitutions, like GDB's `set substitute-path`
+    ///
+    /// They are applied, in order, to the source file path of each [`Location`]; the first one
+    /// whose `from` is a prefix of the path wins. Non-matching paths are left unchanged.
+    pub substitute_paths: Vec<(PathBuf, PathBuf)>,
+
+    /// Split DWARF (`.dwo`) files that hold the debug info of the ELF's skeleton units
+    ///
+    /// Needed when the firmware was built with `-Csplit-debuginfo=unpacked`.
+    pub split_dwarf: Vec<PathBuf>,
+
+    /// Packed split DWARF (`.dwp`) file that holds the debug info of the ELF's skeleton units
+    ///
+    /// Needed when the firmware was built with `-Csplit-debuginfo=packed`.
+    pub dwp: Option<PathBuf>,
+
+    /// Directory that relative source paths are resolved against
+    ///
+    /// Source paths are relative when the compilation directory (`DW_AT_comp_dir`) itself is, e.g.
+    /// in reproducible builds that use `--remap-path-prefix`. If `None`, such paths are left
+    /// relative. The root is applied before `substitute_paths`.
+    pub source_root: Option<PathBuf>,
+}
+
+/// Returns the locations of all log statements in `table`.
+///
+/// Address collisions are resolved by keeping the first location; use [`get_locations_with`] to
+/// find out about them.
 pub fn get_locations(elf: &[u8], table: &Table) -> Result<Locations, anyhow::Error> {
+    get_locations_with(elf, table, &LocationsOptions::default()).map(|(locations, _)| locations)
+}
+
+/// Like [`get_locations`] but with extra [`LocationsOptions`]; also returns the address
+/// collisions that were found, if any.
+pub fn get_locations_with(
+    elf: &[u8],
+    table: &Table,
+    options: &LocationsOptions,
+) -> Result<(Locations, Vec<LocationCollision>), anyhow::Error> {
     let object = object::File::parse(elf)?;
     let endian = if object.is_little_endian() {
         gimli::RunTimeEndian::Little
@@ -222,114 +617,611 @@ pub fn get_locations(elf: &[u8], table: &Table) -> Result<Locations, anyhow::Err
 
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
+    let mut headers = vec![];
     let mut units = dwarf.debug_info.units();
+    while let Some(header) = units.next()? {
+        headers.push(header);
+    }
 
-    let mut map = BTreeMap::new();
+    // units are independent of each other so, optionally, process them in parallel
+    #[cfg(feature = "rayon")]
+    let mut locations = {
+        use rayon::prelude::*;
+
+        headers
+            .par_iter()
+            .map(|header| unit_locations(&dwarf, dwarf.unit(*header)?, table, options))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    #[cfg(not(feature = "rayon"))]
+    let mut locations = headers
+        .iter()
+        .map(|header| unit_locations(&dwarf, dwarf.unit(*header)?, table, options))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !options.split_dwarf.is_empty() || options.dwp.is_some() {
+        // split units are linked to the skeleton units in the ELF by their DWO ID
+        let mut skeletons = BTreeMap::new();
+        for header in headers {
+            let unit = dwarf.unit(header)?;
+            if let Some(dwo_id) = dwo_id(&unit)? {
+                skeletons.insert(dwo_id, unit);
+            }
+        }
+
+        for path in options.split_dwarf.iter().chain(&options.dwp) {
+            let file = fs::read(path)?;
+            let file = object::File::parse(&file[..])?;
+            let section_data = |id: gimli::SectionId| match id
+                .dwo_name()
+                .and_then(|name| file.section_by_name(name))
+            {
+                Some(s) => s.uncompressed_data().unwrap_or(Cow::Borrowed(&[][..])),
+                None => Cow::Borrowed(&[][..]),
+            };
+            let load_sup = |_| Ok(Cow::Borrowed(&[][..]));
+
+            let index = match file.section_by_name(".debug_cu_index") {
+                Some(index) => index.uncompressed_data()?,
+                // a `.dwo` file: all units are split units
+                None => {
+                    let dwo_cow = gimli::Dwarf::<Cow<[u8]>>::load::<_, _, anyhow::Error>(
+                        |id| Ok(section_data(id)),
+                        load_sup,
+                    )?;
+                    let split_dwarf = dwo_cow.borrow(&borrow_section);
+                    locations.push(split_unit_locations(
+                        &dwarf,
+                        split_dwarf,
+                        &skeletons,
+                        table,
+                        options,
+                    )?);
+                    continue;
+                }
+            };
+
+            // a `.dwp` file: look up the split unit of each skeleton unit in the index and load
+            // only its contributions to the sections
+            let index = dwp::UnitIndex::parse(gimli::EndianSlice::new(&index, endian))
+                .map_err(|e| anyhow!("{}: invalid `.debug_cu_index`: {}", path.display(), e))?;
+            for dwo_id in skeletons.keys() {
+                let row = match index.find(*dwo_id) {
+                    Some(row) => row,
+                    None => continue,
+                };
+                let dwp_cow = gimli::Dwarf::<Cow<[u8]>>::load::<_, _, anyhow::Error>(
+                    |id| {
+                        let data = section_data(id);
+                        let range = match index.contribution(row, id) {
+                            Some(range) => range,
+                            None => return Ok(data),
+                        };
+                        ensure!(
+                            range.end <= data.len(),
+                            "{}: the `.debug_cu_index` entry of DWO ID {:#x} exceeds `{}`",
+                            path.display(),
+                            dwo_id,
+                            id.dwo_name().unwrap_or_else(|| id.name())
+                        );
+                        Ok(match data {
+                            Cow::Borrowed(data) => Cow::Borrowed(&data[range]),
+                            Cow::Owned(data) => Cow::Owned(data[range].to_vec()),
+                        })
+                    },
+                    load_sup,
+                )?;
+                let split_dwarf = dwp_cow.borrow(&borrow_section);
+                locations.push(split_unit_locations(
+                    &dwarf,
+                    split_dwarf,
+                    &skeletons,
+                    table,
+                    options,
+                )?);
+            }
+        }
+    }
+
+    Ok(collect_locations(locations.into_iter().flatten()))
+}
+
+/// Builds the [`Locations`] map, keeping the first location of colliding addresses
+///
+/// The order of `locations` is deterministic (units in `.debug_info` order, even when they are
+/// processed in parallel), so is the choice of the kept location.
+fn collect_locations(
+    locations: impl IntoIterator<Item = Location>,
+) -> (Locations, Vec<LocationCollision>) {
+    let mut map = Locations::new();
+    let mut collisions = vec![];
+    for loc in locations {
+        match map.entry(loc.index) {
+            Entry::Vacant(entry) => {
+                entry.insert(loc);
+            }
+            Entry::Occupied(entry) => collisions.push(LocationCollision {
+                kept: entry.get().clone(),
+                discarded: loc,
+            }),
+        }
+    }
+
+    (map, collisions)
+}
+
+/// Resolves the [`Location`] of single log statements on demand
+///
+/// An alternative to [`Table::get_locations`] for short sessions against large ELF files: instead
+/// of walking the debug info of all compilation units up front, units are walked one at a time,
+/// and only until the requested address is found. The locations of all walked units are cached,
+/// so each unit is walked at most once.
+///
+/// The resolver owns a copy of the DWARF sections, so the ELF bytes don't need to outlive it.
+/// Split DWARF is not supported; use [`Table::get_locations_with`] for that.
+pub struct LocationResolver<'t> {
+    table: &'t Table,
+    options: LocationsOptions,
+    endian: gimli::RunTimeEndian,
+    dwarf: gimli::Dwarf<Vec<u8>>,
+    /// Offsets of the units in `.debug_info`, in order
+    units: Vec<gimli::DebugInfoOffset>,
+    /// Index into `units` of the next unit to walk
+    next_unit: usize,
+    cache: Locations,
+}
+
+impl<'t> LocationResolver<'t> {
+    pub fn new(
+        elf: &[u8],
+        table: &'t Table,
+        options: LocationsOptions,
+    ) -> Result<Self, anyhow::Error> {
+        ensure!(
+            options.split_dwarf.is_empty() && options.dwp.is_none(),
+            "split DWARF is not supported by `LocationResolver`; use `get_locations_with`"
+        );
+
+        let object = object::File::parse(elf)?;
+        let endian = if object.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+
+        let load_section = |id: gimli::SectionId| {
+            Ok(match object.section_by_name(id.name()) {
+                Some(s) => s
+                    .uncompressed_data()
+                    .map(Cow::into_owned)
+                    .unwrap_or_default(),
+                None => vec![],
+            })
+        };
+        let dwarf =
+            gimli::Dwarf::<Vec<u8>>::load::<_, _, anyhow::Error>(&load_section, |_| Ok(vec![]))?;
+
+        let mut units = vec![];
+        let mut headers = dwarf
+            .borrow(|section| gimli::EndianSlice::new(section, endian))
+            .debug_info
+            .units();
+        while let Some(header) = headers.next()? {
+            if let Some(offset) = header.offset().as_debug_info_offset() {
+                units.push(offset);
+            }
+        }
+
+        Ok(Self {
+            table,
+            options,
+            endian,
+            dwarf,
+            units,
+            next_unit: 0,
+            cache: Locations::new(),
+        })
+    }
+
+    /// Returns the location of the log statement at `address`, or `None` if the debug info
+    /// doesn't have one.
+    ///
+    /// Like [`Table::get_locations`], the first location wins if several claim the same address.
+    pub fn resolve(&mut self, address: u64) -> Result<Option<&Location>, anyhow::Error> {
+        let endian = self.endian;
+        let dwarf = self
+            .dwarf
+            .borrow(|section| gimli::EndianSlice::new(section, endian));
+
+        while !self.cache.contains_key(&address) && self.next_unit < self.units.len() {
+            let header = dwarf
+                .debug_info
+                .header_from_offset(self.units[self.next_unit])?;
+            self.next_unit += 1;
+
+            let locations = unit_locations(&dwarf, dwarf.unit(header)?, self.table, &self.options)?;
+            for location in locations {
+                self.cache.entry(location.index).or_insert(location);
+            }
+        }
+
+        Ok(self.cache.get(&address))
+    }
+
+    /// Returns the locations resolved so far
+    pub fn cached(&self) -> &Locations {
+        &self.cache
+    }
+}
+
+/// Collects the locations of the split units of `split_dwarf` (a `.dwo` file, or one unit of a
+/// `.dwp` file) whose skeleton unit is in `skeletons`
+fn split_unit_locations<'a>(
+    dwarf: &gimli::Dwarf<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    mut split_dwarf: gimli::Dwarf<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    skeletons: &BTreeMap<u64, gimli::Unit<gimli::EndianSlice<'a, gimli::RunTimeEndian>>>,
+    table: &Table,
+    options: &LocationsOptions,
+) -> Result<Vec<Location>, anyhow::Error> {
+    split_dwarf.file_type = gimli::DwarfFileType::Dwo;
+    // `.debug_addr` never lives in a `.dwo` file
+    split_dwarf.debug_addr = dwarf.debug_addr;
+
+    let mut locations = vec![];
+    let mut units = split_dwarf.debug_info.units();
     while let Some(header) = units.next()? {
-        let unit = dwarf.unit(header)?;
-        let abbrev = header.abbreviations(&dwarf.debug_abbrev)?;
+        let mut unit = split_dwarf.unit(header)?;
+        let skeleton = match dwo_id(&unit)?.and_then(|dwo_id| skeletons.get(&dwo_id)) {
+            Some(skeleton) => skeleton,
+            // not part of this ELF
+            None => continue,
+        };
+        unit.copy_relocated_attributes(skeleton);
+        if unit.comp_dir.is_none() {
+            unit.comp_dir = skeleton.comp_dir;
+        }
+        // split units have no `DW_AT_stmt_list`; their line table is the `.dwo`'s one
+        if unit.line_program.is_none() {
+            unit.line_program = Some(split_dwarf.debug_line.program(
+                gimli::DebugLineOffset(0),
+                unit.header.address_size(),
+                unit.comp_dir,
+                unit.name,
+            )?);
+        }
 
-        let mut cursor = header.entries(&abbrev);
+        locations.extend(unit_locations(&split_dwarf, unit, table, options)?);
+    }
 
-        ensure!(cursor.next_dfs()?.is_some(), "empty DWARF?");
+    Ok(locations)
+}
 
-        let mut segments = vec![];
-        let mut depth = 0;
-        while let Some((delta_depth, entry)) = cursor.next_dfs()? {
-            depth += delta_depth;
+/// Returns the DWO ID that links a skeleton unit to its split unit, and vice versa
+fn dwo_id(
+    unit: &gimli::Unit<gimli::EndianSlice<gimli::RunTimeEndian>>,
+) -> Result<Option<u64>, anyhow::Error> {
+    // DWARF 5 puts it in the unit header
+    if let gimli::UnitType::Skeleton(dwo_id) | gimli::UnitType::SplitCompilation(dwo_id) =
+        unit.header.type_()
+    {
+        return Ok(Some(dwo_id.0));
+    }
 
-            // NOTE .. here start the custom logic
-            if entry.tag() == gimli::constants::DW_TAG_namespace {
-                let mut attrs = entry.attrs();
+    // the GNU extension to DWARF 4 puts it in the unit's root DIE
+    let mut cursor = unit.entries();
+    if let Some((_, root)) = cursor.next_dfs()? {
+        if let Some(gimli::AttributeValue::DwoId(dwo_id)) =
+            root.attr_value(gimli::constants::DW_AT_GNU_dwo_id)?
+        {
+            return Ok(Some(dwo_id.0));
+        }
+    }
 
-                while let Some(attr) = attrs.next()? {
-                    if attr.name() == gimli::constants::DW_AT_name {
-                        if let gimli::AttributeValue::DebugStrRef(off) = attr.value() {
-                            let s = dwarf.string(off)?;
-                            for _ in (depth as usize)..segments.len() + 1 {
-                                segments.pop();
-                            }
-                            segments.push(core::str::from_utf8(&s)?.to_string());
+    Ok(None)
+}
+
+/// Collects the locations of all log statements in a single compilation unit.
+fn unit_locations(
+    dwarf: &gimli::Dwarf<gimli::EndianSlice<gimli::RunTimeEndian>>,
+    unit: gimli::Unit<gimli::EndianSlice<gimli::RunTimeEndian>>,
+    table: &Table,
+    options: &LocationsOptions,
+) -> Result<Vec<Location>, anyhow::Error> {
+    let mut cursor = unit.entries();
+
+    ensure!(cursor.next_dfs()?.is_some(), "empty DWARF?");
+
+    // many log statements share a source file; resolve each file index only once
+    let mut files = HashMap::<u64, SourceFile>::new();
+
+    // all variables of the unit, so that the attributes of inlined variables can be looked up in
+    // their abstract origin (which can come before or after the concrete variable)
+    let mut variables = vec![];
+    let mut segments = vec![];
+    let mut depth = 0;
+    while let Some((delta_depth, entry)) = cursor.next_dfs()? {
+        depth += delta_depth;
+
+        // NOTE .. here start the custom logic
+        if entry.tag() == gimli::constants::DW_TAG_namespace {
+            let mut attrs = entry.attrs();
+
+            while let Some(attr) = attrs.next()? {
+                if attr.name() == gimli::constants::DW_AT_name {
+                    if let Ok(s) = dwarf.attr_string(&unit, attr.value()) {
+                        for _ in (depth as usize)..segments.len() + 1 {
+                            segments.pop();
                         }
+                        segments.push(core::str::from_utf8(&s)?.to_string());
                     }
                 }
-            } else if entry.tag() == gimli::constants::DW_TAG_variable {
-                // Iterate over the attributes in the DIE.
-                let mut attrs = entry.attrs();
-
-                // what we are after
-                let mut decl_file = None;
-                let mut decl_line = None; // line number
-                let mut name = None;
-                let mut linkage_name = None;
-                let mut location = None;
-
-                while let Some(attr) = attrs.next()? {
-                    match attr.name() {
-                        gimli::constants::DW_AT_name => {
-                            if let gimli::AttributeValue::DebugStrRef(off) = attr.value() {
-                                name = Some(off);
-                            }
-                        }
-                        gimli::constants::DW_AT_decl_file => {
-                            if let gimli::AttributeValue::FileIndex(idx) = attr.value() {
-                                decl_file = Some(idx);
-                            }
-                        }
-                        gimli::constants::DW_AT_decl_line => {
-                            if let gimli::AttributeValue::Udata(line) = attr.value() {
-                                decl_line = Some(line);
-                            }
-                        }
-                        gimli::constants::DW_AT_location => {
-                            if let gimli::AttributeValue::Exprloc(loc) = attr.value() {
-                                location = Some(loc);
-                            }
+            }
+        } else if entry.tag() == gimli::constants::DW_TAG_variable {
+            // Iterate over the attributes in the DIE.
+            let mut attrs = entry.attrs();
+
+            // what we are after
+            let mut variable = Variable {
+                offset: entry.offset(),
+                module: segments.join("::"),
+                name: None,
+                linkage_name: None,
+                decl_file: None,
+                decl_line: None, // line number
+                decl_column: None,
+                location: None,
+                origin: None,
+            };
+
+            while let Some(attr) = attrs.next()? {
+                match attr.name() {
+                    gimli::constants::DW_AT_name => {
+                        variable.name = dwarf.attr_string(&unit, attr.value()).ok();
+                    }
+                    gimli::constants::DW_AT_decl_file => {
+                        variable.decl_file = attr_constant(attr.value());
+                    }
+                    gimli::constants::DW_AT_decl_line => {
+                        variable.decl_line = attr_constant(attr.value());
+                    }
+                    gimli::constants::DW_AT_decl_column => {
+                        variable.decl_column = attr_constant(attr.value());
+                    }
+                    gimli::constants::DW_AT_location => {
+                        if let gimli::AttributeValue::Exprloc(loc) = attr.value() {
+                            variable.location = Some(loc);
                         }
-                        gimli::constants::DW_AT_linkage_name => {
-                            if let gimli::AttributeValue::DebugStrRef(off) = attr.value() {
-                                linkage_name = Some(off);
-                            }
+                    }
+                    gimli::constants::DW_AT_linkage_name => {
+                        variable.linkage_name = dwarf.attr_string(&unit, attr.value()).ok();
+                    }
+                    gimli::constants::DW_AT_abstract_origin
+                    | gimli::constants::DW_AT_specification => {
+                        if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                            variable.origin = Some(offset);
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
+            }
 
-                if let (
-                    Some(name_index),
-                    Some(linkage_name_index),
-                    Some(file_index),
-                    Some(line),
-                    Some(loc),
-                ) = (name, linkage_name, decl_file, decl_line, location)
-                {
-                    let name_slice = dwarf.string(name_index)?;
-                    let name = core::str::from_utf8(&name_slice)?;
-                    let linkage_name_slice = dwarf.string(linkage_name_index)?;
-                    let linkage_name = core::str::from_utf8(&linkage_name_slice)?;
-
-                    if name == "DEFMT_LOG_STATEMENT" {
-                        if table.raw_symbols().any(|i| i == linkage_name) {
-                            let addr = exprloc2address(unit.encoding(), &loc)?;
-                            let file = file_index_to_path(file_index, &unit, &dwarf)?;
-                            let module = segments.join("::");
-
-                            let loc = Location { file, line, module };
-
-                            if let Some(old) = map.insert(addr, loc.clone()) {
-                                bail!("BUG in DWARF variable filter: index collision for addr 0x{:08x} (old = {:?}, new = {:?})", addr, old, loc);
-                            }
-                        } else {
-                            // this symbol was GC-ed by the linker (but remains in the DWARF info)
-                            // so we discard it (its `addr` info is also wrong which causes collisions)
+            variables.push(variable);
+        }
+    }
+
+    let by_offset = variables
+        .iter()
+        .enumerate()
+        .map(|(i, variable)| (variable.offset, i))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut locations = vec![];
+    for variable in &variables {
+        // only concrete variables have an address
+        if variable.location.is_none() {
+            continue;
+        }
+
+        // a variable in an inlined function (or the definition of a declaration) only has some
+        // of the attributes; the rest, and the module it was written in, are in its origin
+        let mut variable = variable.clone();
+        let mut origin = variable.origin;
+        for _ in 0..MAX_ORIGIN_DEPTH {
+            let resolved = match origin.and_then(|offset| by_offset.get(&offset)) {
+                Some(&i) => &variables[i],
+                None => break,
+            };
+            variable.inherit(resolved);
+            origin = resolved.origin;
+        }
+
+        if let Variable {
+            name: Some(name_slice),
+            linkage_name: Some(linkage_name_slice),
+            decl_file: Some(file_index),
+            decl_line: Some(line),
+            decl_column,
+            location: Some(loc),
+            module,
+            ..
+        } = variable
+        {
+            let name = core::str::from_utf8(&name_slice)?;
+            let linkage_name = core::str::from_utf8(&linkage_name_slice)?;
+
+            if name == "DEFMT_LOG_STATEMENT" {
+                if table.raw_symbols().any(|i| i == linkage_name) {
+                    let addr = match exprloc2address(dwarf, &unit, &loc) {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            // don't let one odd expression cost all other log statements their
+                            // location
+                            log::warn!("skipping the location of `{}`: {}", linkage_name, e);
+                            continue;
                         }
-                    }
+                    };
+                    let SourceFile {
+                        path: file,
+                        crate_name,
+                        crate_version,
+                    } = match files.get(&file_index) {
+                        Some(file) => file.clone(),
+                        None => {
+                            let file = resolve_file(file_index, &unit, dwarf, options)?;
+                            files.insert(file_index, file.clone());
+                            file
+                        }
+                    };
+
+                    let loc = Location {
+                        index: addr,
+                        file,
+                        line,
+                        column: decl_column,
+                        module,
+                        crate_name,
+                        crate_version,
+                        object: None,
+                    };
+
+                    locations.push(loc);
+                } else {
+                    // this symbol was GC-ed by the linker (but remains in the DWARF info)
+                    // so we discard it (its `addr` info is also wrong which causes collisions)
                 }
             }
         }
     }
 
-    Ok(map)
+    Ok(locations)
+}
+
+/// A source file of a unit, as it ends up in a [`Location`]
+#[derive(Clone)]
+struct SourceFile {
+    path: PathBuf,
+    crate_name: Option<String>,
+    crate_version: Option<String>,
+}
+
+/// Resolves the file `index` of `unit`, applying the `options`
+fn resolve_file(
+    index: u64,
+    unit: &gimli::Unit<gimli::EndianSlice<gimli::RunTimeEndian>>,
+    dwarf: &gimli::Dwarf<gimli::EndianSlice<gimli::RunTimeEndian>>,
+    options: &LocationsOptions,
+) -> Result<SourceFile, anyhow::Error> {
+    let mut file = file_index_to_path(index, unit, dwarf)?;
+    if let (true, Some(root)) = (file.is_relative(), &options.source_root) {
+        file = normalize_path(&root.join(file));
+    }
+    // before substitution, which may well rewrite the registry path
+    let (crate_name, crate_version) = match registry_crate(&file) {
+        Some((name, version)) => (Some(name), Some(version)),
+        None => (None, None),
+    };
+    Ok(SourceFile {
+        path: substitute_path(file, &options.substitute_paths),
+        crate_name,
+        crate_version,
+    })
+}
+
+/// Upper bound on the length of `DW_AT_abstract_origin` / `DW_AT_specification` chains, in case
+/// the debug info contains a cycle
+const MAX_ORIGIN_DEPTH: usize = 8;
+
+/// The attributes of a `DW_TAG_variable` DIE that `unit_locations` is after
+#[derive(Clone)]
+struct Variable<'a> {
+    offset: gimli::UnitOffset,
+    /// Path of the namespace the variable is in
+    module: String,
+    name: Option<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    linkage_name: Option<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    decl_file: Option<u64>,
+    decl_line: Option<u64>,
+    decl_column: Option<u64>,
+    location: Option<gimli::Expression<gimli::EndianSlice<'a, gimli::RunTimeEndian>>>,
+    /// `DW_AT_abstract_origin` or `DW_AT_specification`
+    origin: Option<gimli::UnitOffset>,
+}
+
+impl Variable<'_> {
+    /// Fills in the attributes this variable doesn't have from its `origin`, and takes over its
+    /// module as that's where the variable was declared.
+    fn inherit(&mut self, origin: &Self) {
+        self.name = self.name.or(origin.name);
+        self.linkage_name = self.linkage_name.or(origin.linkage_name);
+        self.decl_file = self.decl_file.or(origin.decl_file);
+        self.decl_line = self.decl_line.or(origin.decl_line);
+        self.decl_column = self.decl_column.or(origin.decl_column);
+        self.module = origin.module.clone();
+    }
+}
+
+/// Returns the value of an integer-valued attribute, like `DW_AT_decl_file` or `DW_AT_decl_line`
+///
+/// gimli turns the constant forms of the standard attributes into `FileIndex` and `Udata`, but
+/// producers are free to use any constant form (`DW_FORM_data*`, `DW_FORM_sdata`, ..), so accept
+/// all of them.
+fn attr_constant<R: gimli::Reader>(value: gimli::AttributeValue<R>) -> Option<u64> {
+    match value {
+        gimli::AttributeValue::FileIndex(value) | gimli::AttributeValue::Udata(value) => {
+            Some(value)
+        }
+        value => value.udata_value(),
+    }
+}
+
+/// Replaces the first matching prefix in `substitutions` (see [`LocationsOptions`])
+fn substitute_path(path: PathBuf, substitutions: &[(PathBuf, PathBuf)]) -> PathBuf {
+    for (from, to) in substitutions {
+        if let Ok(rest) = path.strip_prefix(from) {
+            return to.join(rest);
+        }
+    }
+
+    path
+}
+
+/// Extracts the name and version of a crates.io crate from the path of one of its source files,
+/// e.g. `~/.cargo/registry/src/<index>/cortex-m-rt-0.7.3/src/lib.rs`
+fn registry_crate(path: &Path) -> Option<(String, String)> {
+    let mut components = path
+        .components()
+        .map(|component| component.as_os_str().to_str());
+    while let Some(component) = components.next() {
+        if component == Some("registry") && components.next()? == Some("src") {
+            // skip the index directory (`github.com-1ecc6299db9ec823`, `index.crates.io-...`)
+            components.next()?;
+            return split_crate_dir(components.next()??);
+        }
+    }
+    None
+}
+
+/// Splits a `<name>-<version>` directory name; both parts may contain dashes (`sha-1-0.10.0`,
+/// `foo-1.0.0-alpha.1`) but the version starts with `<major>.<minor>.<patch>`.
+fn split_crate_dir(dir: &str) -> Option<(String, String)> {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_version = |s: &str| {
+        let mut parts = s.splitn(3, '.');
+        match (parts.next(), parts.next(), parts.next()) {
+            // the patch version may be followed by a pre-release or build suffix
+            (Some(major), Some(minor), Some(patch)) => {
+                is_number(major)
+                    && is_number(minor)
+                    && patch.starts_with(|c: char| c.is_ascii_digit())
+            }
+            _ => false,
+        }
+    };
+
+    dir.match_indices('-')
+        .map(|(i, _)| (&dir[..i], &dir[i + 1..]))
+        .find(|(name, version)| !name.is_empty() && is_version(version))
+        .map(|(name, version)| (name.to_string(), version.to_string()))
 }
 
 fn file_index_to_path<R>(
@@ -340,7 +1232,11 @@ fn file_index_to_path<R>(
 where
     R: gimli::read::Reader,
 {
-    ensure!(index != 0, "`FileIndex` was zero");
+    // file index 0 is reserved before DWARF 5; from DWARF 5 on it refers to the primary source file
+    ensure!(
+        index != 0 || unit.encoding().version >= 5,
+        "`FileIndex` was zero"
+    );
 
     let header = if let Some(program) = &unit.line_program {
         program.header()
@@ -374,21 +1270,751 @@ where
             .to_string_lossy()?[..],
     );
 
-    Ok(p)
+    Ok(normalize_path(&p))
+}
+
+/// Lexically resolves `.` and `..` components of `path`, without accessing the file system
+///
+/// Leading `..` components of a relative path are kept; `..` at the root stays at the root.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                Some(Component::ParentDir) | Some(Component::CurDir) | None => {
+                    normalized.push("..")
+                }
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
 }
 
 fn exprloc2address<R: gimli::read::Reader<Offset = usize>>(
-    encoding: gimli::Encoding,
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
     data: &gimli::Expression<R>,
+) -> Result<u64, anyhow::Error> {
+    expression_address(data, unit.encoding(), |index| {
+        Ok(dwarf.address(unit, index)?)
+    })
+}
+
+/// Finds the address in a location expression
+///
+/// Besides literal addresses (`DW_OP_addr`), this handles indices into the `.debug_addr` section
+/// (`DW_OP_addrx` and `DW_OP_constx`, used by DWARF 5 and split DWARF), which are looked up with
+/// `resolve_index`.
+fn expression_address<R: gimli::read::Reader<Offset = usize>>(
+    data: &gimli::Expression<R>,
+    encoding: gimli::Encoding,
+    resolve_index: impl Fn(gimli::DebugAddrIndex<usize>) -> Result<u64, anyhow::Error>,
 ) -> Result<u64, anyhow::Error> {
     let mut pc = data.0.clone();
     while pc.len() != 0 {
-        if let Ok(gimli::Operation::Address { address }) =
-            gimli::Operation::parse(&mut pc, encoding)
-        {
-            return Ok(address);
+        match gimli::Operation::parse(&mut pc, encoding)? {
+            gimli::Operation::Address { address } => return Ok(address),
+            gimli::Operation::AddressIndex { index }
+            | gimli::Operation::ConstantIndex { index } => return resolve_index(index),
+            _ => {}
         }
     }
 
     Err(anyhow!("`Operation::Address` not found"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::test_elf::{
+        dwo_with_units, dwp_with_units, elf_header, elf_with_sections, elf_with_units,
+        elf_with_units_and_sections, section_header, TestUnit, ELF_HEADER_SIZE,
+        SECTION_HEADER_SIZE, SHT_PROGBITS, SHT_STRTAB,
+    };
+    use super::*;
+
+    #[test]
+    fn parse_error() {
+        assert!(matches!(
+            Table::parse(b"definitely not an ELF file"),
+            Err(ParseError::ObjectParse(_))
+        ));
+    }
+
+    /// Builds a 64-bit ELF file with a `.shstrtab` and a `.defmt` section at `defmt_range`
+    fn elf_with_defmt_section(defmt_range: (u64, u64)) -> Vec<u8> {
+        let strtab = b"\0.shstrtab\0.defmt\0";
+        let strtab_offset = ELF_HEADER_SIZE + 3 * SECTION_HEADER_SIZE;
+
+        let mut elf = elf_header(3, 1);
+        section_header(&mut elf, 0, 0, (0, 0), (0, 0), 0);
+        let strtab_range = (strtab_offset, strtab.len() as u64);
+        section_header(&mut elf, 1, SHT_STRTAB, strtab_range, (0, 0), 0);
+        section_header(&mut elf, 11, SHT_PROGBITS, defmt_range, (0, 0), 0);
+        elf.extend_from_slice(strtab);
+        elf
+    }
+
+    /// Returns the name of the version symbol that matches this decoder
+    fn version_symbol() -> String {
+        format!("_defmt_version_ = {}", DEFMT_VERSION)
+    }
+
+    /// Returns the name of a defmt symbol
+    fn defmt_symbol(tag: &str, data: &str, disambiguator: u32) -> String {
+        format!(
+            r#"{{"package":"app","tag":"{}","data":"{}","disambiguator":"{}"}}"#,
+            tag, data, disambiguator
+        )
+    }
+
+    #[test]
+    fn truncated() {
+        let len = elf_with_defmt_section((0, 0)).len() as u64;
+
+        let mut complete = elf_with_defmt_section((len, 4));
+        complete.extend_from_slice(&[0; 4]);
+        // no version symbol, but otherwise fine
+        assert!(matches!(
+            Table::parse(&complete),
+            Err(ParseError::MissingVersionSymbol { .. })
+        ));
+
+        let truncated = elf_with_defmt_section((len, 4));
+        match Table::parse(&truncated) {
+            Err(ParseError::Truncated {
+                section,
+                end,
+                file_len,
+            }) => {
+                assert_eq!(section, ".defmt");
+                assert_eq!(end, len + 4);
+                assert_eq!(file_len, len);
+            }
+            _ => panic!("expected a `Truncated` error"),
+        }
+    }
+
+    /// Builds a table with an info-level entry for each of the defmt `symbols` (address, name)
+    fn table_with_symbols(symbols: &[(usize, &str)]) -> Table {
+        let entries = symbols
+            .iter()
+            .map(|(address, symbol)| {
+                let string = StringEntry::new(Tag::Info, "log statement".to_string());
+                (*address, TableEntry::new(string, symbol.to_string()))
+            })
+            .collect();
+        Table::new(entries)
+    }
+
+    /// The parts of a [`Location`] that the tests look at
+    fn location_parts(location: &Location) -> (u64, &Path, u64, &str) {
+        (
+            location.index,
+            &location.file,
+            location.line,
+            &location.module,
+        )
+    }
+
+    #[test]
+    fn symbol_map() {
+        let info = defmt_symbol("defmt_info", "hello", 1);
+        let warn = defmt_symbol("defmt_warn", "bye", 2);
+        let sections: &[(&str, &[u8])] = &[(".defmt", &[0; 4])];
+        // any version, even one this decoder can't decode
+        let version = "_defmt_version_ = 0.1";
+        let elf = elf_with_sections(
+            sections,
+            &[(version, None, 0), (&info, Some(0), 1), (&warn, Some(0), 3)],
+        );
+
+        let map = super::symbol_map(&elf).unwrap();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), [(1, info), (3, warn)]);
+
+        let elf = elf_with_sections(&[(".text", &[0; 4])], &[]);
+        assert!(super::symbol_map(&elf).unwrap().is_empty());
+    }
+
+    #[test]
+    fn partitioned_defmt_section() {
+        let version = version_symbol();
+        let info = defmt_symbol("defmt_info", "hello", 1);
+        let warn = defmt_symbol("defmt_warn", "bye", 2);
+        let sections: &[(&str, &[u8])] = &[(".defmt", &[0; 2]), (".defmt.1", &[0; 2])];
+
+        // the entries of all parts end up in the same table
+        let elf = elf_with_sections(
+            sections,
+            &[
+                (&version, None, 0),
+                (&info, Some(0), 1),
+                (&warn, Some(1), 3),
+            ],
+        );
+        let table = Table::parse(&elf).unwrap().unwrap();
+        assert_eq!(table.entries.len(), 2);
+        let (frame, _) = table.decode(&[1]).unwrap();
+        assert_eq!(frame.display_message().to_string(), "hello");
+        let (frame, _) = table.decode(&[3]).unwrap();
+        assert_eq!(frame.display_message().to_string(), "bye");
+
+        // parts that claim the same address can't be told apart when decoding
+        let elf = elf_with_sections(
+            sections,
+            &[
+                (&version, None, 0),
+                (&info, Some(0), 1),
+                (&warn, Some(1), 1),
+            ],
+        );
+        match Table::parse(&elf) {
+            Err(ParseError::DuplicateAddress {
+                address,
+                first,
+                second,
+            }) => {
+                assert_eq!(address, 1);
+                assert_eq!(first, info);
+                assert_eq!(second, warn);
+            }
+            _ => panic!("expected a `DuplicateAddress` error"),
+        }
+    }
+
+    #[test]
+    fn location_resolver_without_debug_info() {
+        let len = elf_with_defmt_section((0, 0)).len() as u64;
+        let elf = elf_with_defmt_section((len, 0));
+        let table = Table::new(BTreeMap::new());
+
+        let mut resolver =
+            LocationResolver::new(&elf, &table, LocationsOptions::default()).unwrap();
+        assert!(resolver.resolve(0x1000).unwrap().is_none());
+        assert!(resolver.cached().is_empty());
+
+        let options = LocationsOptions {
+            split_dwarf: vec!["app.dwo".into()],
+            ..LocationsOptions::default()
+        };
+        assert!(LocationResolver::new(&elf, &table, options).is_err());
+    }
+
+    #[test]
+    fn location_resolver() {
+        let a = defmt_symbol("defmt_info", "a", 1);
+        let b = defmt_symbol("defmt_info", "b", 2);
+        let table = table_with_symbols(&[(0x10, &a), (0x20, &b)]);
+
+        let mut resolver = {
+            let mut first = TestUnit::new();
+            first.add_log_statement(first.namespace, &a, 0x10, 1);
+            let mut second = TestUnit::new();
+            second.add_log_statement(second.namespace, &b, 0x20, 2);
+            // a bogus second location for `a`; the first unit's wins
+            second.add_log_statement(second.namespace, &a, 0x10, 9);
+
+            // the resolver doesn't borrow the ELF
+            let elf = elf_with_units(vec![first, second]);
+            LocationResolver::new(&elf, &table, LocationsOptions::default()).unwrap()
+        };
+        assert_eq!(resolver.units.len(), 2);
+
+        let location = resolver.resolve(0x10).unwrap().unwrap();
+        let expected = (0x10, Path::new("/app/src/main.rs"), 1, "app");
+        assert_eq!(location_parts(location), expected);
+        // the second unit has not been walked yet
+        assert_eq!(resolver.next_unit, 1);
+        assert_eq!(resolver.cached().len(), 1);
+
+        let location = resolver.resolve(0x20).unwrap().unwrap();
+        assert_eq!(location.line, 2);
+        assert_eq!(resolver.next_unit, 2);
+        assert_eq!(resolver.cached()[&0x10].line, 1);
+
+        // cached; nothing left to walk either way
+        assert_eq!(resolver.resolve(0x20).unwrap().unwrap().line, 2);
+        assert!(resolver.resolve(0x30).unwrap().is_none());
+        assert_eq!(resolver.next_unit, 2);
+    }
+
+    #[test]
+    fn location_resolver_matches_get_locations() {
+        let symbols = (0..4)
+            .map(|i| defmt_symbol("defmt_info", "x", i))
+            .collect::<Vec<_>>();
+        let addresses = [0x10, 0x14, 0x18, 0x1c];
+        let table = table_with_symbols(&[
+            (addresses[0], &symbols[0]),
+            (addresses[1], &symbols[1]),
+            (addresses[2], &symbols[2]),
+            (addresses[3], &symbols[3]),
+        ]);
+
+        let units = (0..2)
+            .map(|i| {
+                let mut unit = TestUnit::new();
+                for j in 0..2 {
+                    let k = 2 * i + j;
+                    let line = 10 * k as u64 + 1;
+                    unit.add_log_statement(unit.namespace, &symbols[k], addresses[k] as u64, line);
+                }
+                unit
+            })
+            .collect();
+        let elf = elf_with_units(units);
+
+        let locations = get_locations(&elf, &table).unwrap();
+        assert_eq!(locations.len(), 4);
+        // in reverse, so that the first lookup walks all units
+        let mut resolver =
+            LocationResolver::new(&elf, &table, LocationsOptions::default()).unwrap();
+        for address in addresses.iter().rev() {
+            let resolved = resolver.resolve(*address as u64).unwrap().unwrap();
+            assert_eq!(
+                location_parts(resolved),
+                location_parts(&locations[&(*address as u64)])
+            );
+        }
+        assert_eq!(resolver.cached().len(), locations.len());
+    }
+
+    #[test]
+    fn attr_constant() {
+        type Value = gimli::AttributeValue<gimli::EndianSlice<'static, gimli::RunTimeEndian>>;
+
+        assert_eq!(super::attr_constant(Value::FileIndex(2)), Some(2));
+        assert_eq!(super::attr_constant(Value::Udata(42)), Some(42));
+        assert_eq!(super::attr_constant(Value::Data1(3)), Some(3));
+        assert_eq!(super::attr_constant(Value::Data2(300)), Some(300));
+        assert_eq!(super::attr_constant(Value::Data4(70_000)), Some(70_000));
+        assert_eq!(super::attr_constant(Value::Sdata(7)), Some(7));
+        assert_eq!(super::attr_constant(Value::Sdata(-1)), None);
+        assert_eq!(super::attr_constant(Value::Flag(true)), None);
+    }
+
+    #[test]
+    fn versions_compatible() {
+        assert!(super::versions_compatible("0.2", "0.2"));
+        assert!(super::versions_compatible("0.2.3", "0.2"));
+        assert!(!super::versions_compatible("0.3", "0.2"));
+        assert!(super::versions_compatible("1", "1"));
+        assert!(super::versions_compatible("1.4.0", "1"));
+        assert!(super::versions_compatible("1.0.0-alpha", "1"));
+        assert!(!super::versions_compatible("2", "1"));
+        assert!(!super::versions_compatible("0.1", "1"));
+
+        let rev = "e739d0ac703dfa629a159be329e8c62a1c3ed206";
+        assert!(super::versions_compatible(rev, rev));
+        assert!(!super::versions_compatible(rev, "0.2"));
+        assert!(!super::versions_compatible(
+            rev,
+            "5c4b7cf0b9a97e4cd95ff3a1a9325f5a15c0a7ac"
+        ));
+    }
+
+    #[test]
+    fn is_defmt_section() {
+        assert!(super::is_defmt_section(".defmt", ".defmt"));
+        assert!(super::is_defmt_section(".defmt.1", ".defmt"));
+        assert!(super::is_defmt_section(".defmt.error.2", ".defmt"));
+        assert!(!super::is_defmt_section(".defmtx", ".defmt"));
+        assert!(!super::is_defmt_section(".data", ".defmt"));
+        assert!(super::is_defmt_section(".log.a", ".log"));
+    }
+
+    #[test]
+    fn inherit_from_abstract_origin() {
+        let slice =
+            |s: &'static str| gimli::EndianSlice::new(s.as_bytes(), gimli::RunTimeEndian::Little);
+        let variable = |offset, module: &str| Variable {
+            offset: gimli::UnitOffset(offset),
+            module: module.to_string(),
+            name: None,
+            linkage_name: None,
+            decl_file: None,
+            decl_line: None,
+            decl_column: None,
+            location: None,
+            origin: None,
+        };
+
+        // concrete variable in the inlined copy of `helper::log` in `app::main`
+        let mut concrete = Variable {
+            decl_line: Some(7),
+            origin: Some(gimli::UnitOffset(1)),
+            ..variable(2, "app::main")
+        };
+        let origin = Variable {
+            name: Some(slice("DEFMT_LOG_STATEMENT")),
+            decl_file: Some(1),
+            decl_line: Some(42),
+            decl_column: Some(5),
+            ..variable(1, "helper")
+        };
+
+        concrete.inherit(&origin);
+        assert_eq!(concrete.name, Some(slice("DEFMT_LOG_STATEMENT")));
+        assert_eq!(concrete.decl_file, Some(1));
+        // the concrete variable's own attributes win
+        assert_eq!(concrete.decl_line, Some(7));
+        assert_eq!(concrete.decl_column, Some(5));
+        assert_eq!(concrete.module, "helper");
+    }
+
+    #[test]
+    fn inlined_log_statement() {
+        use gimli::write::{Address, AttributeValue, Expression};
+
+        let symbol = defmt_symbol("defmt_info", "hello", 1);
+        let table = table_with_symbols(&[(0x10, &symbol)]);
+
+        let mut unit = TestUnit::new();
+        let root = unit.unit.root();
+        // `helper::log`, which is always inlined; its variable has no address
+        let helper = unit.unit.add(root, gimli::DW_TAG_namespace);
+        let name = AttributeValue::String(b"helper".to_vec());
+        unit.unit.get_mut(helper).set(gimli::DW_AT_name, name);
+        let log = unit.unit.add(helper, gimli::DW_TAG_subprogram);
+        let abstract_variable = unit.add_log_statement(log, &symbol, 0, 42);
+        unit.unit
+            .get_mut(abstract_variable)
+            .delete(gimli::DW_AT_location);
+
+        // `app::main`, which `helper::log` is inlined into
+        let main = unit.unit.add(unit.namespace, gimli::DW_TAG_subprogram);
+        let inlined = unit.unit.add(main, gimli::DW_TAG_inlined_subroutine);
+        let origin = AttributeValue::UnitRef(log);
+        unit.unit
+            .get_mut(inlined)
+            .set(gimli::DW_AT_abstract_origin, origin);
+        let concrete_variable = unit.unit.add(inlined, gimli::DW_TAG_variable);
+        let concrete_variable = unit.unit.get_mut(concrete_variable);
+        let origin = AttributeValue::UnitRef(abstract_variable);
+        concrete_variable.set(gimli::DW_AT_abstract_origin, origin);
+        let mut location = Expression::new();
+        location.op_addr(Address::Constant(0x10));
+        concrete_variable.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+
+        let elf = elf_with_units(vec![unit]);
+        let locations = get_locations(&elf, &table).unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(
+            location_parts(&locations[&0x10]),
+            (0x10, Path::new("/app/src/main.rs"), 42, "helper")
+        );
+    }
+
+    /// Writes `contents` to a file in the temporary directory, named after this process
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let name = format!("defmt-decoder-{}-{}", std::process::id(), name);
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn split_dwarf() {
+        let a = defmt_symbol("defmt_info", "a", 1);
+        let b = defmt_symbol("defmt_info", "b", 2);
+        let table = table_with_symbols(&[(0x10, &a), (0x20, &b)]);
+        let split_unit = |dwo_id, symbol: &str, address, line| {
+            let mut unit = TestUnit::new();
+            unit.set_dwo_id(dwo_id);
+            unit.add_log_statement(unit.namespace, symbol, address, line);
+            unit
+        };
+
+        // the ELF file only has the skeleton units
+        let skeletons = [1, 9]
+            .iter()
+            .map(|dwo_id| {
+                let mut unit = TestUnit::new();
+                unit.set_dwo_id(*dwo_id);
+                unit
+            })
+            .collect();
+        let elf = elf_with_units(skeletons);
+        assert!(get_locations(&elf, &table).unwrap().is_empty());
+
+        let expected = [
+            (0x10, Path::new("/app/src/main.rs"), 1, "app"),
+            (0x20, Path::new("/app/src/main.rs"), 2, "app"),
+        ];
+        let check = |options| {
+            let (locations, collisions) = get_locations_with(&elf, &table, &options).unwrap();
+            assert!(collisions.is_empty());
+            let locations = locations.values().map(location_parts).collect::<Vec<_>>();
+            assert_eq!(locations, expected);
+        };
+
+        let dwo_files = vec![
+            temp_file("a.dwo", &dwo_with_units(vec![split_unit(1, &a, 0x10, 1)])),
+            temp_file("b.dwo", &dwo_with_units(vec![split_unit(9, &b, 0x20, 2)])),
+        ];
+        let options = LocationsOptions {
+            split_dwarf: dwo_files.clone(),
+            ..LocationsOptions::default()
+        };
+        check(options);
+
+        // DWO IDs 1 and 9 collide in the hash table; unit 5 has no skeleton in the ELF file
+        let dwp = dwp_with_units(vec![
+            (1, split_unit(1, &a, 0x10, 1)),
+            (5, split_unit(5, &b, 0x30, 3)),
+            (9, split_unit(9, &b, 0x20, 2)),
+        ]);
+        let dwp = temp_file("app.dwp", &dwp);
+        let options = LocationsOptions {
+            dwp: Some(dwp.clone()),
+            ..LocationsOptions::default()
+        };
+        check(options);
+
+        for path in dwo_files.iter().chain(Some(&dwp)) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn relative_comp_dir() {
+        let a = defmt_symbol("defmt_info", "a", 1);
+        let table = table_with_symbols(&[(0x10, &a)]);
+
+        // as after `--remap-path-prefix=/home/ferris/app=.`
+        let mut unit = TestUnit::with_comp_dir(".");
+        unit.add_log_statement(unit.namespace, &a, 0x10, 1);
+        let elf = elf_with_units(vec![unit]);
+
+        let locations = get_locations(&elf, &table).unwrap();
+        assert_eq!(
+            location_parts(&locations[&0x10]),
+            (0x10, Path::new("src/main.rs"), 1, "app")
+        );
+
+        let options = LocationsOptions {
+            source_root: Some(PathBuf::from("/home/ferris/app")),
+            ..LocationsOptions::default()
+        };
+        let (locations, _) = get_locations_with(&elf, &table, &options).unwrap();
+        assert_eq!(
+            location_parts(&locations[&0x10]),
+            (0x10, Path::new("/home/ferris/app/src/main.rs"), 1, "app")
+        );
+    }
+
+    #[test]
+    fn address_index() {
+        use gimli::write::{AttributeValue, Expression};
+
+        let a = defmt_symbol("defmt_info", "a", 1);
+        let b = defmt_symbol("defmt_info", "b", 2);
+        let c = defmt_symbol("defmt_info", "c", 3);
+        let table = table_with_symbols(&[(0x10, &a), (0x20, &b), (0x30, &c)]);
+
+        // a DWARF 5 `.debug_addr` section with the addresses 0x20 and 0x10
+        let mut debug_addr = vec![];
+        debug_addr.extend_from_slice(&12u32.to_le_bytes()); // unit_length
+        debug_addr.extend_from_slice(&5u16.to_le_bytes()); // version
+        debug_addr.extend_from_slice(&[4, 0]); // address_size, segment_selector_size
+        for address in [0x20u32, 0x10] {
+            debug_addr.extend_from_slice(&address.to_le_bytes());
+        }
+
+        let mut unit = TestUnit::with_version(5);
+        let root = unit.unit.root();
+        // gimli can't write `DW_AT_addr_base`, but any `DW_FORM_sec_offset` value does
+        let addr_base = AttributeValue::DebugMacinfoRef(gimli::DebugMacinfoOffset(8));
+        unit.unit
+            .get_mut(root)
+            .set(gimli::DW_AT_addr_base, addr_base);
+        let statements = [
+            (&b, 2, vec![gimli::DW_OP_addrx.0, 0]),
+            (&a, 1, vec![gimli::DW_OP_addrx.0, 1]),
+            // the operand is missing
+            (&c, 3, vec![gimli::DW_OP_addrx.0]),
+        ];
+        for (symbol, line, expression) in statements {
+            let id = unit.add_log_statement(unit.namespace, symbol, 0, line);
+            let location = AttributeValue::Exprloc(Expression::raw(expression));
+            unit.unit.get_mut(id).set(gimli::DW_AT_location, location);
+        }
+        let elf = elf_with_units_and_sections(vec![unit], vec![(".debug_addr", debug_addr)]);
+
+        // the malformed expression only costs its own log statement its location
+        let locations = get_locations(&elf, &table).unwrap();
+        assert_eq!(
+            locations.values().map(location_parts).collect::<Vec<_>>(),
+            [
+                (0x10, Path::new("/app/src/main.rs"), 1, "app"),
+                (0x20, Path::new("/app/src/main.rs"), 2, "app"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dwarf5_primary_source_file() {
+        let a = defmt_symbol("defmt_info", "a", 1);
+        let b = defmt_symbol("defmt_info", "b", 2);
+        let table = table_with_symbols(&[(0x10, &a), (0x20, &b)]);
+
+        let mut unit = TestUnit::with_version(5);
+        let id = unit.add_log_statement(unit.namespace, &a, 0x10, 1);
+        // file index 0, which is the primary source file `src/main.rs` from DWARF 5 on
+        let file = gimli::write::AttributeValue::FileIndex(None);
+        unit.unit.get_mut(id).set(gimli::DW_AT_decl_file, file);
+        unit.add_log_statement(unit.namespace, &b, 0x20, 2);
+        let elf = elf_with_units(vec![unit]);
+
+        let locations = get_locations(&elf, &table).unwrap();
+        assert_eq!(
+            locations.values().map(location_parts).collect::<Vec<_>>(),
+            [
+                (0x10, Path::new("/app/src/main.rs"), 1, "app"),
+                (0x20, Path::new("/app/src/main.rs"), 2, "app"),
+            ]
+        );
+    }
+
+    #[test]
+    fn expression_address() {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 5,
+            address_size: 4,
+        };
+        let address = |bytes: &[u8]| {
+            let expression =
+                gimli::Expression(gimli::EndianSlice::new(bytes, gimli::RunTimeEndian::Little));
+            super::expression_address(&expression, encoding, |index| {
+                Ok(0x2000_0000 + index.0 as u64)
+            })
+            .ok()
+        };
+
+        // DW_OP_addr 0x0800_0100
+        assert_eq!(address(&[0x03, 0x00, 0x01, 0x00, 0x08]), Some(0x0800_0100));
+        // DW_OP_addrx 3
+        assert_eq!(address(&[0xa1, 0x03]), Some(0x2000_0003));
+        // DW_OP_constx 5
+        assert_eq!(address(&[0xa2, 0x05]), Some(0x2000_0005));
+        // DW_OP_lit0 DW_OP_addrx 1
+        assert_eq!(address(&[0x30, 0xa1, 0x01]), Some(0x2000_0001));
+        // DW_OP_lit0 only; truncated DW_OP_addr
+        assert_eq!(address(&[0x30]), None);
+        assert_eq!(address(&[0x03, 0x00]), None);
+    }
+
+    #[test]
+    fn collect_locations() {
+        let location = |index, line| Location {
+            index,
+            file: "src/main.rs".into(),
+            line,
+            column: None,
+            module: "app".to_string(),
+            crate_name: None,
+            crate_version: None,
+            object: None,
+        };
+
+        let (map, collisions) = super::collect_locations(vec![
+            location(0x10, 1),
+            location(0x14, 2),
+            location(0x10, 3),
+        ]);
+
+        // the rest of the map is still usable
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&0x10].line, 1);
+        assert_eq!(map[&0x14].line, 2);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].kept.index, 0x10);
+        assert_eq!(collisions[0].discarded.line, 3);
+        assert_eq!(
+            collisions[0].to_string(),
+            "index collision for addr 0x00000010 (kept = src/main.rs:1, discarded = src/main.rs:3)"
+        );
+    }
+
+    #[test]
+    fn normalize_path() {
+        for (path, normalized) in [
+            (
+                "/builds/./app/src/../src/main.rs",
+                "/builds/app/src/main.rs",
+            ),
+            ("/../main.rs", "/main.rs"),
+            ("./src/main.rs", "src/main.rs"),
+            // `--remap-path-prefix` with a relative `comp_dir`
+            ("./../app/./src/main.rs", "../app/src/main.rs"),
+            ("src/../../main.rs", "../main.rs"),
+        ] {
+            assert_eq!(
+                super::normalize_path(Path::new(path)),
+                PathBuf::from(normalized)
+            );
+        }
+    }
+
+    #[test]
+    fn registry_crate() {
+        for (path, krate) in [
+            (
+                "/home/me/.cargo/registry/src/github.com-1ecc6299db9ec823/cortex-m-rt-0.7.3/src/lib.rs",
+                Some(("cortex-m-rt", "0.7.3")),
+            ),
+            (
+                "/cargo/registry/src/index.crates.io-6f17d22bba15001f/sha-1-0.10.0/src/lib.rs",
+                Some(("sha-1", "0.10.0")),
+            ),
+            (
+                "/cargo/registry/src/index/foo-1.0.0-alpha.1/src/lib.rs",
+                Some(("foo", "1.0.0-alpha.1")),
+            ),
+            ("/home/me/app/src/main.rs", None),
+            ("/home/me/registry/app/src/main.rs", None),
+            ("/cargo/registry/src/index", None),
+        ] {
+            assert_eq!(
+                super::registry_crate(Path::new(path)),
+                krate.map(|(name, version)| (name.to_string(), version.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn substitute_path() {
+        let substitutions = vec![
+            (
+                PathBuf::from("/builds/xyz/src"),
+                PathBuf::from("/home/me/src"),
+            ),
+            (PathBuf::from("/builds"), PathBuf::from("/other")),
+        ];
+
+        // first match wins
+        assert_eq!(
+            super::substitute_path("/builds/xyz/src/main.rs".into(), &substitutions),
+            PathBuf::from("/home/me/src/main.rs")
+        );
+        assert_eq!(
+            super::substitute_path("/builds/abc/lib.rs".into(), &substitutions),
+            PathBuf::from("/other/abc/lib.rs")
+        );
+        // prefixes match whole components only
+        assert_eq!(
+            super::substitute_path("/buildsx/lib.rs".into(), &substitutions),
+            PathBuf::from("/buildsx/lib.rs")
+        );
+        assert_eq!(
+            super::substitute_path("/rustc/lib.rs".into(), &substitutions),
+            PathBuf::from("/rustc/lib.rs")
+        );
+    }
+}
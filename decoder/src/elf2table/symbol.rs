@@ -53,6 +53,10 @@ impl Symbol {
         }
     }
 
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
     pub fn data(&self) -> &str {
         &self.data
     }
@@ -0,0 +1,144 @@
+//! Standalone table files: a parsed [`Table`] and its [`Locations`], for decoding logs without the
+//! ELF file they came from.
+
+use std::io;
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+
+use super::Locations;
+use crate::Table;
+
+/// Version of the table file format; bumped on incompatible changes
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct TableFileRef<'a> {
+    format_version: u32,
+    table: &'a Table,
+    locations: Option<&'a Locations>,
+}
+
+#[derive(Deserialize)]
+struct TableFile {
+    format_version: u32,
+    table: Table,
+    locations: Option<Locations>,
+}
+
+/// Writes `table` and (if available) its `locations` to `writer`, in a format that
+/// [`read_table_file`] can read back.
+///
+/// The file contains all format strings of the firmware, but nothing else of the ELF file.
+pub fn write_table_file(
+    writer: impl io::Write,
+    table: &Table,
+    locations: Option<&Locations>,
+) -> Result<(), anyhow::Error> {
+    serde_json::to_writer(
+        writer,
+        &TableFileRef {
+            format_version: FORMAT_VERSION,
+            table,
+            locations,
+        },
+    )?;
+    Ok(())
+}
+
+/// Reads a table file written by [`write_table_file`].
+pub fn read_table_file(reader: impl io::Read) -> Result<(Table, Option<Locations>), anyhow::Error> {
+    let file: TableFile = serde_json::from_reader(reader)?;
+    ensure!(
+        file.format_version == FORMAT_VERSION,
+        "unsupported table file format version {} (expected {})",
+        file.format_version,
+        FORMAT_VERSION
+    );
+    let mut locations = file.locations;
+    // files written before `Location` had an `index` only have it as the key
+    for (index, location) in locations.iter_mut().flatten() {
+        location.index = *index;
+    }
+    Ok((file.table, locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{Location, TableEntry, Tag};
+
+    #[test]
+    fn round_trip() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Info, "x={=u8}".to_string()),
+        );
+        let table = Table::new(entries);
+        let mut locations = Locations::new();
+        locations.insert(
+            1,
+            Location {
+                index: 1,
+                file: "src/main.rs".into(),
+                line: 3,
+                column: Some(5),
+                module: "app".to_string(),
+                crate_name: None,
+                crate_version: None,
+                object: None,
+            },
+        );
+
+        let mut file = vec![];
+        write_table_file(&mut file, &table, Some(&locations)).unwrap();
+        let (read_table, read_locations) = read_table_file(&file[..]).unwrap();
+
+        let frame = read_table.decode(&[1, 42]).unwrap().0;
+        assert_eq!(frame.display_message().to_string(), "x=42");
+        assert_eq!(read_locations.unwrap()[&1].line, 3);
+
+        let file = String::from_utf8(file)
+            .unwrap()
+            .replace("\"format_version\":1", "\"format_version\":2");
+        assert!(read_table_file(file.as_bytes()).is_err());
+    }
+
+    /// Files written before `Location` had an `index` get it from the key of the location
+    #[test]
+    fn locations_without_index() {
+        let table = Table::new(BTreeMap::new());
+        let mut locations = Locations::new();
+        for index in [7, 300] {
+            let location = Location {
+                index,
+                file: "src/main.rs".into(),
+                line: 3,
+                column: None,
+                module: "app".to_string(),
+                crate_name: None,
+                crate_version: None,
+                object: None,
+            };
+            locations.insert(index, location);
+        }
+
+        let mut file = vec![];
+        write_table_file(&mut file, &table, Some(&locations)).unwrap();
+        let legacy = String::from_utf8(file)
+            .unwrap()
+            .replace("\"index\":7,", "")
+            .replace("\"index\":300,", "");
+        assert!(!legacy.contains("\"index\""));
+
+        let (_, read_locations) = read_table_file(legacy.as_bytes()).unwrap();
+        let read_locations = read_locations.unwrap();
+        assert_eq!(read_locations.len(), 2);
+        for (key, location) in &read_locations {
+            assert_eq!(location.index, *key);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Hand-built ELF files with DWARF debug info
+//!
+//! Shared by the tests of this module and by the benchmarks, which include this file with
+//! `#[path]`, so it must only depend on `std` and `gimli`.
+
+pub const ELF_HEADER_SIZE: u64 = 64;
+pub const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+pub const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+
+/// Builds the header of a 64-bit little-endian ELF file whose section header table directly
+/// follows the header
+pub fn elf_header(shnum: u16, shstrndx: u16) -> Vec<u8> {
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1];
+    elf.resize(16, 0);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // e_type: executable
+    elf.extend_from_slice(&0x28u16.to_le_bytes()); // e_machine
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&[0; 16]); // e_entry, e_phoff
+    elf.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for half in [
+        ELF_HEADER_SIZE as u16,
+        0,
+        0,
+        SECTION_HEADER_SIZE as u16,
+        shnum,
+        shstrndx,
+    ] {
+        // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    elf
+}
+
+/// Appends a section header; `link`, `info` and `entsize` only matter for symbol tables
+pub fn section_header(
+    elf: &mut Vec<u8>,
+    name: u32,
+    ty: u32,
+    (offset, size): (u64, u64),
+    (link, info): (u32, u32),
+    entsize: u64,
+) {
+    elf.extend_from_slice(&name.to_le_bytes());
+    elf.extend_from_slice(&ty.to_le_bytes());
+    elf.extend_from_slice(&[0; 16]); // sh_flags, sh_addr
+    elf.extend_from_slice(&offset.to_le_bytes());
+    elf.extend_from_slice(&size.to_le_bytes());
+    elf.extend_from_slice(&link.to_le_bytes());
+    elf.extend_from_slice(&info.to_le_bytes());
+    elf.extend_from_slice(&[0; 8]); // sh_addralign
+    elf.extend_from_slice(&entsize.to_le_bytes());
+}
+
+/// Builds a 64-bit ELF file with `sections` (name and contents) and a symbol table with
+/// `symbols`
+///
+/// Each symbol is given by its name, the index into `sections` of the section it is in (`None`
+/// for an absolute symbol) and its address.
+pub fn elf_with_sections(
+    sections: &[(&str, &[u8])],
+    symbols: &[(&str, Option<usize>, u64)],
+) -> Vec<u8> {
+    // section 0 is the null section; `sections` follow, then these three
+    let symtab_index = sections.len() + 1;
+    let strtab_index = symtab_index + 1;
+    let shstrtab_index = strtab_index + 1;
+    let shnum = shstrtab_index + 1;
+
+    let mut shstrtab = vec![0];
+    let mut names = vec![];
+    let extra = [".symtab", ".strtab", ".shstrtab"];
+    for name in sections.iter().map(|(name, _)| *name).chain(extra) {
+        names.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+
+    let mut strtab = vec![0];
+    // starts with the null symbol
+    let mut symtab = vec![0; SYMBOL_SIZE as usize];
+    for (name, section, address) in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes()); // st_name
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+        symtab.push(0x11); // st_info: STB_GLOBAL, STT_OBJECT
+        symtab.push(0); // st_other
+        let shndx = section.map_or(0xfff1 /* SHN_ABS */, |index| index as u16 + 1);
+        symtab.extend_from_slice(&shndx.to_le_bytes());
+        symtab.extend_from_slice(&address.to_le_bytes());
+        symtab.extend_from_slice(&1u64.to_le_bytes()); // st_size
+    }
+
+    let mut contents = sections.iter().map(|(_, data)| *data).collect::<Vec<_>>();
+    contents.extend_from_slice(&[&symtab, &strtab, &shstrtab]);
+
+    let mut elf = elf_header(shnum as u16, shstrtab_index as u16);
+    section_header(&mut elf, 0, 0, (0, 0), (0, 0), 0);
+    let mut data = vec![];
+    let data_offset = ELF_HEADER_SIZE + shnum as u64 * SECTION_HEADER_SIZE;
+    for (i, contents) in contents.iter().enumerate() {
+        // `object` reads the symbol table in place, so keep everything aligned
+        data.resize((data.len() + 7) & !7, 0);
+        let range = (data_offset + data.len() as u64, contents.len() as u64);
+        data.extend_from_slice(contents);
+
+        let index = i + 1;
+        if index == symtab_index {
+            let link = (strtab_index as u32, 1);
+            section_header(&mut elf, names[i], SHT_SYMTAB, range, link, SYMBOL_SIZE);
+        } else if index == strtab_index || index == shstrtab_index {
+            section_header(&mut elf, names[i], SHT_STRTAB, range, (0, 0), 0);
+        } else {
+            section_header(&mut elf, names[i], SHT_PROGBITS, range, (0, 0), 0);
+        }
+    }
+    elf.extend_from_slice(&data);
+    elf
+}
+
+/// A compilation unit of `src/main.rs`, compiled in `/app` unless stated otherwise, written with
+/// `gimli::write`
+pub struct TestUnit {
+    pub unit: gimli::write::Unit,
+    /// The `app` namespace
+    pub namespace: gimli::write::UnitEntryId,
+    pub file: gimli::write::FileId,
+}
+
+impl TestUnit {
+    pub fn new() -> Self {
+        Self::with_version(4)
+    }
+
+    /// Like [`TestUnit::new`], but with DWARF `version` instead of 4
+    pub fn with_version(version: u16) -> Self {
+        Self::build(version, "/app")
+    }
+
+    /// Like [`TestUnit::new`], but compiled in `comp_dir` instead of `/app`
+    pub fn with_comp_dir(comp_dir: &str) -> Self {
+        Self::build(4, comp_dir)
+    }
+
+    fn build(version: u16, comp_dir: &str) -> Self {
+        use gimli::write::{AttributeValue, LineProgram, LineString, Unit};
+
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version,
+            address_size: 4,
+        };
+        let comp_dir = comp_dir.as_bytes().to_vec();
+        let mut program = LineProgram::new(
+            encoding,
+            gimli::LineEncoding::default(),
+            LineString::String(comp_dir.clone()),
+            LineString::String(b"src/main.rs".to_vec()),
+            None,
+        );
+        let directory = program.default_directory();
+        let file_name = LineString::String(b"src/main.rs".to_vec());
+        let file = program.add_file(file_name, directory, None);
+
+        let mut unit = Unit::new(encoding, program);
+        let root = unit.root();
+        let attr = AttributeValue::String(comp_dir);
+        unit.get_mut(root).set(gimli::DW_AT_comp_dir, attr);
+        let namespace = unit.add(root, gimli::DW_TAG_namespace);
+        let attr = AttributeValue::String(b"app".to_vec());
+        unit.get_mut(namespace).set(gimli::DW_AT_name, attr);
+
+        Self {
+            unit,
+            namespace,
+            file,
+        }
+    }
+
+    /// Sets the DWO ID (`DW_AT_GNU_dwo_id`) that links a skeleton unit and its split unit
+    pub fn set_dwo_id(&mut self, dwo_id: u64) {
+        let root = self.unit.root();
+        let attr = gimli::write::AttributeValue::Data8(dwo_id);
+        self.unit.get_mut(root).set(gimli::DW_AT_GNU_dwo_id, attr);
+    }
+
+    /// Adds a `DEFMT_LOG_STATEMENT` variable at `address`, for the defmt `symbol`, declared
+    /// at `line` of `src/main.rs`
+    pub fn add_log_statement(
+        &mut self,
+        parent: gimli::write::UnitEntryId,
+        symbol: &str,
+        address: u64,
+        line: u64,
+    ) -> gimli::write::UnitEntryId {
+        use gimli::write::{Address, AttributeValue, Expression};
+
+        let id = self.unit.add(parent, gimli::DW_TAG_variable);
+        let variable = self.unit.get_mut(id);
+        let name = b"DEFMT_LOG_STATEMENT".to_vec();
+        variable.set(gimli::DW_AT_name, AttributeValue::String(name));
+        let linkage_name = symbol.as_bytes().to_vec();
+        variable.set(
+            gimli::DW_AT_linkage_name,
+            AttributeValue::String(linkage_name),
+        );
+        let file = AttributeValue::FileIndex(Some(self.file));
+        variable.set(gimli::DW_AT_decl_file, file);
+        variable.set(gimli::DW_AT_decl_line, AttributeValue::Udata(line));
+        let mut location = Expression::new();
+        location.op_addr(Address::Constant(address));
+        variable.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+        id
+    }
+}
+
+/// Writes the DWARF sections of `units`
+fn dwarf_sections(units: Vec<TestUnit>) -> Vec<(gimli::SectionId, Vec<u8>)> {
+    use gimli::write::{Dwarf, EndianVec, Sections};
+
+    let mut dwarf = Dwarf::new();
+    for unit in units {
+        dwarf.units.add(unit.unit);
+    }
+    let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+
+    let mut contents = vec![];
+    sections
+        .for_each(|id, data| {
+            contents.push((id, data.slice().to_vec()));
+            Ok::<_, gimli::write::Error>(())
+        })
+        .unwrap();
+    contents
+}
+
+/// Builds an ELF file with the given sections (name and contents) and no symbols
+fn elf_with_named_sections(sections: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let sections = sections
+        .iter()
+        .map(|(name, data)| (*name, &data[..]))
+        .collect::<Vec<_>>();
+    elf_with_sections(&sections, &[])
+}
+
+/// Builds an ELF file with the DWARF sections of `units`
+pub fn elf_with_units(units: Vec<TestUnit>) -> Vec<u8> {
+    elf_with_units_and_sections(units, vec![])
+}
+
+/// Builds an ELF file with the DWARF sections of `units` and the `extra` sections (name and
+/// contents), e.g. DWARF sections that `gimli::write` can't write
+pub fn elf_with_units_and_sections(units: Vec<TestUnit>, extra: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+    let sections = dwarf_sections(units)
+        .into_iter()
+        .map(|(id, data)| (id.name(), data))
+        .chain(extra)
+        .collect::<Vec<_>>();
+    elf_with_named_sections(&sections)
+}
+
+/// Builds a split DWARF (`.dwo`) file with the split `units`
+pub fn dwo_with_units(units: Vec<TestUnit>) -> Vec<u8> {
+    let sections = dwarf_sections(units)
+        .into_iter()
+        .filter_map(|(id, data)| Some((id.dwo_name()?, data)))
+        .collect::<Vec<_>>();
+    elf_with_named_sections(&sections)
+}
+
+/// Builds a packed split DWARF (`.dwp`) file with the split `units`, each given with its DWO ID,
+/// and a version 2 (GNU) `.debug_cu_index`
+pub fn dwp_with_units(units: Vec<(u64, TestUnit)>) -> Vec<u8> {
+    // the sections that `TestUnit`s contribute to, with their `DW_SECT_*` identifier
+    let columns = [
+        (gimli::SectionId::DebugInfo, 1),
+        (gimli::SectionId::DebugAbbrev, 3),
+        (gimli::SectionId::DebugLine, 4),
+    ];
+
+    let mut contents = vec![vec![]; columns.len()];
+    let mut offsets = vec![];
+    let mut sizes = vec![];
+    let mut dwo_ids = vec![];
+    for (dwo_id, unit) in units {
+        let sections = dwarf_sections(vec![unit]);
+        for ((id, _), contents) in columns.iter().zip(&mut contents) {
+            let data = sections
+                .iter()
+                .find(|(section, _)| section == id)
+                .map_or(&[][..], |(_, data)| &data[..]);
+            offsets.push(contents.len() as u32);
+            sizes.push(data.len() as u32);
+            contents.extend_from_slice(data);
+        }
+        dwo_ids.push(dwo_id);
+    }
+
+    // open addressing with double hashing, see DWARF 5 section 7.3.5.3
+    let slots = (2 * dwo_ids.len()).next_power_of_two();
+    let mask = slots as u64 - 1;
+    let mut signatures = vec![0; slots];
+    let mut rows = vec![0; slots];
+    for (row, dwo_id) in dwo_ids.iter().enumerate() {
+        let step = ((dwo_id >> 32) & mask) | 1;
+        let mut slot = dwo_id & mask;
+        while rows[slot as usize] != 0 {
+            slot = (slot + step) & mask;
+        }
+        signatures[slot as usize] = *dwo_id;
+        rows[slot as usize] = row as u32 + 1;
+    }
+
+    let mut index = vec![];
+    let header = [2, columns.len() as u32, dwo_ids.len() as u32, slots as u32];
+    for word in header {
+        index.extend_from_slice(&word.to_le_bytes());
+    }
+    for signature in signatures {
+        index.extend_from_slice(&signature.to_le_bytes());
+    }
+    let section_ids = columns.iter().map(|(_, section_id)| *section_id);
+    for word in rows
+        .into_iter()
+        .chain(section_ids)
+        .chain(offsets)
+        .chain(sizes)
+    {
+        index.extend_from_slice(&word.to_le_bytes());
+    }
+
+    let mut sections = columns
+        .iter()
+        .zip(contents)
+        .map(|((id, _), data)| (id.dwo_name().unwrap(), data))
+        .collect::<Vec<_>>();
+    sections.push((".debug_cu_index", index));
+    elf_with_named_sections(&sections)
+}
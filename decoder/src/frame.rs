@@ -7,6 +7,7 @@ use std::{
 use crate::Arg;
 use colored::Colorize;
 use defmt_parser::{DisplayHint, Fragment, Level, ParserMode, Type};
+use serde::Serialize;
 
 /// A log frame
 #[derive(Debug, PartialEq)]
@@ -55,6 +56,18 @@ impl<'t> Frame<'t> {
         })
     }
 
+    /// Returns the value of this frame's timestamp, in the units of the timestamp format (e.g.
+    /// microseconds for `{=u64:us}`).
+    ///
+    /// Returns `None` if the firmware defines no timestamp or if the first argument of its format
+    /// is not an unsigned integer.
+    pub fn timestamp(&self) -> Option<u128> {
+        match self.timestamp_args.first()? {
+            Arg::Uxx(value) => Some(*value),
+            _ => None,
+        }
+    }
+
     /// Returns a struct that will format the message contained in this log frame.
     pub fn display_message(&'t self) -> DisplayMessage<'t> {
         DisplayMessage {
@@ -70,6 +83,138 @@ impl<'t> Frame<'t> {
     pub fn index(&self) -> u64 {
         self.index
     }
+
+    /// Returns the arguments of this frame's message, each tagged with its type.
+    ///
+    /// Unlike [`Frame::display_message`] this keeps the structure of the data intact, which makes
+    /// it suitable for machine-readable output. See [`TypedArg`] for the serialized form.
+    pub fn typed_args(&self) -> Vec<TypedArg<'t>> {
+        typed_args(self.format, &self.args)
+    }
+
+    /// Returns `true` if this frame was produced by `defmt::panic!` or one of the macros built on
+    /// top of it (`assert!`, `unwrap!`, `todo!`, ...).
+    pub fn is_panic(&self) -> bool {
+        self.level == Level::Error && self.format.starts_with(PANIC_PREFIX)
+    }
+}
+
+/// Prefix that `defmt-macros` puts in front of every panic message (e.g. `panicked at 'boom'`).
+const PANIC_PREFIX: &str = "panicked at '";
+
+/// Version of the serialized form of [`TypedArg`]; bumped on every incompatible change.
+pub const TYPED_ARGS_SCHEMA_VERSION: u32 = 1;
+
+/// A decoded argument together with its type, as returned by [`Frame::typed_args`].
+///
+/// Serializes as `{"type": "u32", "value": 42}`. The `type` is the one used in the format string
+/// (`u8`, `i32`, `f32`, `bool`, `char`, `str`, `istr`, `[u8]`, `?`, `[?]`, ...).
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TypedArg<'t> {
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub value: ArgValue<'t>,
+}
+
+/// Value of a [`TypedArg`]
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ArgValue<'t> {
+    Bool(bool),
+    F32(f32),
+    F64(f64),
+    Unsigned(u128),
+    Signed(i128),
+    /// Strings, interned strings, chars and on-target formatted (`Debug2Format`) values
+    Str(String),
+    /// Byte slices and arrays, base64 encoded
+    Bytes(String),
+    /// A value that implements `defmt::Format`
+    Format {
+        format: &'t str,
+        args: Vec<TypedArg<'t>>,
+    },
+    /// A slice or array of values that implement `defmt::Format`
+    List(Vec<ArgValue<'t>>),
+}
+
+fn typed_args<'t>(format: &'t str, args: &[Arg<'t>]) -> Vec<TypedArg<'t>> {
+    let params = defmt_parser::parse(format, ParserMode::ForwardsCompatible).unwrap();
+
+    // arguments can be referred to more than once (e.g. bitfields); the first use decides the type
+    let mut types = vec![None; args.len()];
+    for param in params {
+        if let Fragment::Parameter(param) = param {
+            if let Some(ty) = types.get_mut(param.index) {
+                ty.get_or_insert(param.ty);
+            }
+        }
+    }
+
+    args.iter()
+        .zip(types)
+        .map(|(arg, ty)| TypedArg {
+            ty: ty.as_ref().map(type_name).unwrap_or("?"),
+            value: arg_value(arg),
+        })
+        .collect()
+}
+
+fn type_name(ty: &Type) -> &'static str {
+    match ty {
+        Type::BitField(_) => "bitfield",
+        Type::Bool => "bool",
+        Type::Char => "char",
+        Type::Debug => "debug",
+        Type::Display => "display",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::Format => "?",
+        Type::FormatArray(_) | Type::FormatSlice => "[?]",
+        Type::I8 => "i8",
+        Type::I16 => "i16",
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::I128 => "i128",
+        Type::Isize => "isize",
+        Type::IStr => "istr",
+        Type::Str => "str",
+        Type::U8 => "u8",
+        Type::U16 => "u16",
+        Type::U24 => "u24",
+        Type::U32 => "u32",
+        Type::U64 => "u64",
+        Type::U128 => "u128",
+        Type::Usize => "usize",
+        Type::U8Slice | Type::U8Array(_) => "[u8]",
+    }
+}
+
+fn arg_value<'t>(arg: &Arg<'t>) -> ArgValue<'t> {
+    match arg {
+        Arg::Bool(x) => ArgValue::Bool(x.get()),
+        Arg::F32(x) => ArgValue::F32(*x),
+        Arg::F64(x) => ArgValue::F64(*x),
+        Arg::Uxx(x) => ArgValue::Unsigned(*x),
+        Arg::Ixx(x) => ArgValue::Signed(*x),
+        Arg::Str(x) | Arg::Preformatted(x) => ArgValue::Str(x.clone()),
+        Arg::IStr(x) => ArgValue::Str(x.to_string()),
+        Arg::Char(c) => ArgValue::Str(c.to_string()),
+        Arg::Slice(bytes) => ArgValue::Bytes(base64::encode(bytes)),
+        Arg::Format { format, args } => ArgValue::Format {
+            format,
+            args: typed_args(format, args),
+        },
+        Arg::FormatSlice { elements } => ArgValue::List(
+            elements
+                .iter()
+                .map(|element| ArgValue::Format {
+                    format: element.format,
+                    args: typed_args(element.format, &element.args),
+                })
+                .collect(),
+        ),
+    }
 }
 
 pub struct DisplayMessage<'t> {
@@ -17,6 +17,7 @@ mod decoder;
 mod elf2table;
 mod frame;
 pub mod log;
+mod stream;
 
 use std::{
     collections::BTreeMap,
@@ -29,14 +30,19 @@ use std::{
 };
 
 use decoder::{read_leb128, Decoder};
-use defmt_parser::Level;
-use elf2table::parse_impl;
+use elf2table::{parse_impl, DEFAULT_SECTION_NAME};
+use serde::{Deserialize, Serialize};
 
-pub use elf2table::{Location, Locations};
-pub use frame::Frame;
+pub use defmt_parser::Level;
+pub use elf2table::{
+    read_elf, read_table_file, section_names, symbol_map, write_table_file, ElfBytes, Location,
+    LocationCollision, LocationResolver, Locations, LocationsOptions, ParseError,
+};
+pub use frame::{ArgValue, Frame, TypedArg, TYPED_ARGS_SCHEMA_VERSION};
+pub use stream::{FrameStreamDecoder, Skipped};
 
 /// Specifies the origin of a format string
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Tag {
     /// Defmt-controlled format string for primitive types.
     Prim,
@@ -70,7 +76,7 @@ impl Tag {
 }
 
 /// Entry in [`Table`] combining a format string with it's raw symbol
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TableEntry {
     string: StringEntry,
     raw_symbol: String,
@@ -91,7 +97,7 @@ impl TableEntry {
 }
 
 /// A format string and it's [`Tag`]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct StringEntry {
     tag: Tag,
     string: String,
@@ -103,11 +109,28 @@ impl StringEntry {
     }
 }
 
+/// Version of the serialized form of [`Table`] and [`Location`]; bumped whenever a field is added,
+/// removed or changes meaning.
+///
+/// Tools that cache serialized tables should make it part of the cache key: older data may still
+/// deserialize (new fields have defaults), but lacks information that a fresh parse would have.
+pub const SERDE_FORMAT_VERSION: u32 = 2;
+
 /// Internal table that holds log levels and maps format strings to indices
-#[derive(Debug)]
+///
+/// Implements `Serialize` and `Deserialize` so that tools can cache parsed tables; see
+/// [`SERDE_FORMAT_VERSION`].
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Table {
     timestamp: Option<TableEntry>,
     entries: BTreeMap<usize, TableEntry>,
+    version: Option<String>,
+}
+
+impl AsRef<Table> for Table {
+    fn as_ref(&self) -> &Table {
+        self
+    }
 }
 
 impl Table {
@@ -116,27 +139,56 @@ impl Table {
         Self {
             entries,
             timestamp: None,
+            version: None,
         }
     }
 
     /// Parses an ELF file and returns the decoded `defmt` table.
     ///
     /// This function returns `None` if the ELF file contains no `.defmt` section.
-    pub fn parse(elf: &[u8]) -> Result<Option<Table>, anyhow::Error> {
-        parse_impl(elf, true)
+    pub fn parse(elf: &[u8]) -> Result<Option<Table>, ParseError> {
+        parse_impl(elf, true, DEFAULT_SECTION_NAME)
+    }
+
+    /// Like `parse`, but looks for the defmt symbols in `section_name` instead of `.defmt`.
+    ///
+    /// This is for firmware whose linker script renames the output section of `defmt.x`. Only the
+    /// section name changes: the `_defmt_version_` symbol must still be defined (inside or outside
+    /// of that section), as `defmt.x` does.
+    pub fn parse_with_section(elf: &[u8], section_name: &str) -> Result<Option<Table>, ParseError> {
+        parse_impl(elf, true, section_name)
     }
 
     /// Like `parse`, but does not verify that the defmt version in the firmware matches the host.
     ///
     /// CAUTION: This is meant for defmt/probe-run development only and can result in reading garbage data.
-    pub fn parse_ignore_version(elf: &[u8]) -> Result<Option<Table>, anyhow::Error> {
-        parse_impl(elf, false)
+    pub fn parse_ignore_version(elf: &[u8]) -> Result<Option<Table>, ParseError> {
+        parse_impl(elf, false, DEFAULT_SECTION_NAME)
     }
 
     pub fn set_timestamp_entry(&mut self, timestamp: TableEntry) {
         self.timestamp = Some(timestamp);
     }
 
+    pub fn set_version(&mut self, version: String) {
+        self.version = Some(version);
+    }
+
+    /// Returns the defmt version the firmware was built with (the value of its `_defmt_version_`
+    /// symbol), if the table was parsed from an ELF file.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Returns `true` if firmware that uses defmt `version` (e.g. the one returned by
+    /// [`Table::version`]) can be decoded by this version of the decoder.
+    ///
+    /// Pre-1.0 versions are compatible if their major and minor versions match, later versions
+    /// if their major versions match, and git revisions only if they are identical.
+    pub fn is_compatible_version(version: &str) -> bool {
+        elf2table::is_compatible_version(version)
+    }
+
     fn _get(&self, index: usize) -> Result<(Option<Level>, &str), ()> {
         let entry = self.entries.get(&index).ok_or(())?;
         Ok((entry.string.tag.to_level(), &entry.string.string))
@@ -147,6 +199,23 @@ impl Table {
         Ok((lvl.ok_or(())?, format))
     }
 
+    /// Returns the level and format string of the log statement at `index`
+    ///
+    /// Returns `None` if `index` is not in the table or does not belong to a log statement (e.g.
+    /// if it is an interned string or the format string of a `Format` implementation).
+    pub fn index_to_format(&self, index: usize) -> Option<(Level, &str)> {
+        self.get_with_level(index).ok()
+    }
+
+    /// Returns the string at `index` if it is not a log statement, i.e. an interned string
+    /// (`{=istr}`) or the format string of a `Format` implementation
+    ///
+    /// Together with [`Table::index_to_format`] this lets tools render frames with their own
+    /// logic, without going through [`Frame`].
+    pub fn index_to_string(&self, index: usize) -> Option<&str> {
+        self.get_without_level(index).ok()
+    }
+
     fn get_without_level(&self, index: usize) -> Result<&str, ()> {
         let (lvl, format) = self._get(index)?;
         if lvl.is_none() {
@@ -175,10 +244,55 @@ impl Table {
         self.entries.values().map(|s| &*s.raw_symbol)
     }
 
+    /// Returns the raw symbol of the entry at `index`, i.e. its (JSON-encoded) symbol name in the
+    /// `.defmt` section
+    pub fn raw_symbol(&self, index: usize) -> Option<&str> {
+        self.entries.get(&index).map(|entry| &*entry.raw_symbol)
+    }
+
     pub fn get_locations(&self, elf: &[u8]) -> Result<Locations, anyhow::Error> {
         elf2table::get_locations(elf, self)
     }
 
+    /// Like [`Table::get_locations`] but with extra [`LocationsOptions`], e.g. source path
+    /// substitutions
+    ///
+    /// Also returns the address collisions that were found, if any: log statements whose debug
+    /// info claims an address that another log statement already has. Only the first location of
+    /// each collision is in the returned [`Locations`]; `get_locations` drops the others silently.
+    pub fn get_locations_with(
+        &self,
+        elf: &[u8],
+        options: &LocationsOptions,
+    ) -> Result<(Locations, Vec<LocationCollision>), anyhow::Error> {
+        elf2table::get_locations_with(elf, self, options)
+    }
+
+    /// Returns a [`LocationResolver`] that looks up the locations of log statements on demand,
+    /// instead of all at once like [`Table::get_locations`]
+    pub fn location_resolver(
+        &self,
+        elf: &[u8],
+        options: LocationsOptions,
+    ) -> Result<LocationResolver<'_>, anyhow::Error> {
+        LocationResolver::new(elf, self, options)
+    }
+
+    /// Best-effort alternative to [`Table::get_locations`] for ELF files without debug info.
+    ///
+    /// Recovers the originating object file ([`Location::object`]) and crate ([`Location::module`])
+    /// of each log statement from the contents of a linker map file (`-Map` with GNU `ld` or
+    /// `lld`). Map files have no source information, so `file` is always empty and `line` is
+    /// always `0`.
+    pub fn get_locations_from_map(&self, map: &str) -> Locations {
+        elf2table::get_locations_from_map(map, self)
+    }
+
+    /// Returns a [`FrameStreamDecoder`] that decodes frames from a byte stream using this table.
+    pub fn new_stream_decoder(&self) -> FrameStreamDecoder<'_> {
+        FrameStreamDecoder::new(self)
+    }
+
     /// decode the data sent by the device using the previosuly stored metadata
     ///
     /// * bytes: contains the data sent by the device that logs.
@@ -190,7 +304,7 @@ impl Table {
         let len = bytes.len();
         let index = read_leb128(&mut bytes)?;
 
-        let mut decoder = Decoder::new(self, bytes);
+        let mut decoder = Decoder::new(self, bytes, len);
 
         let mut timestamp_format = None;
         let mut timestamp_args = Vec::new();
@@ -200,9 +314,12 @@ impl Table {
             timestamp_args = decoder.decode_format(format)?;
         }
 
-        let (level, format) = self
-            .get_with_level(index as usize)
-            .map_err(|_| DecodeError::Malformed)?;
+        let (level, format) =
+            self.get_with_level(index as usize)
+                .map_err(|_| DecodeError::Malformed {
+                    offset: 0,
+                    context: "unknown log string index",
+                })?;
 
         let args = decoder.decode_format(format)?;
         if !decoder.bools_tbd.is_empty() {
@@ -227,6 +344,10 @@ impl Bool {
     fn set(&self, value: bool) {
         self.0.store(value, atomic::Ordering::Relaxed);
     }
+
+    fn get(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
 }
 
 impl fmt::Display for Bool {
@@ -288,7 +409,13 @@ pub enum DecodeError {
     /// More data is needed to decode the next frame.
     UnexpectedEof,
 
-    Malformed,
+    /// The data does not form a valid frame.
+    Malformed {
+        /// Offset, in bytes from the start of the frame, of the data that could not be decoded.
+        offset: usize,
+        /// What the decoder was trying to decode, e.g. "unknown interned string index".
+        context: &'static str,
+    },
 }
 
 impl From<io::Error> for DecodeError {
@@ -296,7 +423,10 @@ impl From<io::Error> for DecodeError {
         if e.kind() == io::ErrorKind::UnexpectedEof {
             DecodeError::UnexpectedEof
         } else {
-            DecodeError::Malformed
+            DecodeError::Malformed {
+                offset: 0,
+                context: "I/O error",
+            }
         }
     }
 }
@@ -305,7 +435,9 @@ impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DecodeError::UnexpectedEof => f.write_str("unexpected end of stream"),
-            DecodeError::Malformed => f.write_str("malformed data"),
+            DecodeError::Malformed { offset, context } => {
+                write!(f, "malformed data at byte {}: {}", offset, context)
+            }
         }
     }
 }
@@ -330,6 +462,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -340,6 +473,26 @@ mod tests {
         assert_eq!(frame.display(false).to_string(), expectation.to_owned());
     }
 
+    #[test]
+    fn index_to_format() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Warn, "x={=istr}".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Str, "interned".to_owned()),
+        );
+        let table = Table::new(entries);
+
+        assert_eq!(table.index_to_format(0), Some((Level::Warn, "x={=istr}")));
+        assert_eq!(table.index_to_format(1), None);
+        assert_eq!(table.index_to_format(2), None);
+        assert_eq!(table.index_to_string(0), None);
+        assert_eq!(table.index_to_string(1), Some("interned"));
+    }
+
     #[test]
     fn decode() {
         let mut entries = BTreeMap::new();
@@ -357,6 +510,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: None,
         };
 
@@ -403,6 +557,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: None,
         };
 
@@ -468,6 +623,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: None,
         };
         let bytes = [
@@ -526,6 +682,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: None,
         };
 
@@ -568,6 +725,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -586,6 +744,7 @@ mod tests {
             frame.display(false).to_string(),
             "0.000002 INFO x=Foo { x: 42 }"
         );
+        assert_eq!(frame.timestamp(), Some(2));
     }
 
     #[test]
@@ -723,6 +882,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -1007,6 +1167,7 @@ mod tests {
 
         let table = Table {
             entries,
+            version: None,
             timestamp: Some(TableEntry::new_without_symbol(
                 Tag::Timestamp,
                 "{=u8:µs}".to_owned(),
@@ -1035,4 +1196,101 @@ mod tests {
         let frame = table.decode(&bytes).unwrap().0;
         assert_eq!(frame.display(false).to_string(), "0.000001 INFO x=None");
     }
+
+    #[test]
+    fn panic_frames() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Error, "panicked at 'explicit panic'".to_owned()),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Error, "panicked while sleeping".to_owned()),
+        );
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Info, "panicked at 'not really'".to_owned()),
+        );
+
+        let table = Table {
+            entries,
+            version: None,
+            timestamp: None,
+        };
+
+        assert!(table.decode(&[0]).unwrap().0.is_panic());
+        assert!(!table.decode(&[1]).unwrap().0.is_panic());
+        assert!(!table.decode(&[2]).unwrap().0.is_panic());
+    }
+
+    #[test]
+    fn typed_args() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(
+                Tag::Info,
+                "{=u16} {=i8} {=bool} {=[u8]} {=?}".to_owned(),
+            ),
+        );
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Derived, "Foo {{ x: {=u8} }}".to_owned()),
+        );
+
+        let table = Table {
+            entries,
+            version: None,
+            timestamp: None,
+        };
+
+        let bytes = [
+            0,    // index
+            0x01, // u16
+            0x01, // u16
+            0xff, // i8
+            1,    // length of [u8]
+            42,   // [u8]
+            1,    // index of the struct
+            7,    // Foo.x
+            1,    // bool
+        ];
+
+        let frame = table.decode(&bytes).unwrap().0;
+        assert_eq!(
+            serde_json::to_string(&frame.typed_args()).unwrap(),
+            r#"[{"type":"u16","value":257},{"type":"i8","value":-1},{"type":"bool","value":true},{"type":"[u8]","value":"Kg=="},{"type":"?","value":{"format":"Foo {{ x: {=u8} }}","args":[{"type":"u8","value":7}]}}]"#
+        );
+    }
+
+    #[test]
+    fn malformed_offset() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new_without_symbol(Tag::Info, "{=u8} {=istr}".to_owned()),
+        );
+
+        let table = Table {
+            entries,
+            version: None,
+            timestamp: None,
+        };
+
+        assert_eq!(
+            table.decode(&[1]),
+            Err(DecodeError::Malformed {
+                offset: 0,
+                context: "unknown log string index",
+            })
+        );
+        assert_eq!(
+            table.decode(&[0, 42, 99]),
+            Err(DecodeError::Malformed {
+                offset: 2,
+                context: "unknown interned string index",
+            })
+        );
+    }
 }
@@ -15,45 +15,78 @@ use log::{Level, Log, Metadata, Record};
 use std::{
     fmt::{self, Write as _},
     io::{self, Write},
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
 };
 
 const DEFMT_TARGET_MARKER: &str = "defmt@";
+const COLUMN_KEY: &str = "column";
 
 /// Logs a defmt frame using the `log` facade.
+///
+/// The `column`, if any, is attached to the record as the `column` key-value pair.
 pub fn log_defmt(
     frame: &Frame<'_>,
     file: Option<&str>,
     line: Option<u32>,
+    column: Option<u32>,
     module_path: Option<&str>,
 ) {
-    let level = match frame.level() {
-        crate::Level::Trace => Level::Trace,
-        crate::Level::Debug => Level::Debug,
-        crate::Level::Info => Level::Info,
-        crate::Level::Warn => Level::Warn,
-        crate::Level::Error => Level::Error,
-    };
+    log_defmt_with_message(
+        frame,
+        &frame.display_message(),
+        file,
+        line,
+        column,
+        module_path,
+    )
+}
+
+/// Like [`log_defmt`], but logs `message` instead of the frame's own message.
+///
+/// This is useful for tools that post-process the decoded message, e.g. to reformat it.
+pub fn log_defmt_with_message(
+    frame: &Frame<'_>,
+    message: &dyn fmt::Display,
+    file: Option<&str>,
+    line: Option<u32>,
+    column: Option<u32>,
+    module_path: Option<&str>,
+) {
+    let level = log_level(frame.level());
 
     let timestamp = frame
         .display_timestamp()
         .map(|display| display.to_string())
         .unwrap_or_default();
     let target = format!("{}{}", DEFMT_TARGET_MARKER, timestamp);
-    let display = frame.display_message();
 
     log::logger().log(
         &Record::builder()
-            .args(format_args!("{}", display))
+            .args(format_args!("{}", message))
             .level(level)
             .target(&target)
             .module_path(module_path)
             .file(file)
             .line(line)
+            .key_values(&column.map(|column| (COLUMN_KEY, column)))
             .build(),
     );
 }
 
+/// Converts a defmt log level into the equivalent `log` level.
+pub fn log_level(level: crate::Level) -> Level {
+    match level {
+        crate::Level::Trace => Level::Trace,
+        crate::Level::Debug => Level::Debug,
+        crate::Level::Info => Level::Info,
+        crate::Level::Warn => Level::Warn,
+        crate::Level::Error => Level::Error,
+    }
+}
+
 /// Determines whether `metadata` belongs to a log record produced by [`log_defmt`].
 pub fn is_defmt_frame(metadata: &Metadata) -> bool {
     metadata.target().starts_with(DEFMT_TARGET_MARKER)
@@ -107,6 +140,10 @@ impl<'a> DefmtRecord<'a> {
         self.log_record.line()
     }
 
+    pub fn column(&self) -> Option<u32> {
+        column(self.log_record)
+    }
+
     /// Returns a builder that can format this record for displaying it to the user.
     pub fn printer(&'a self) -> Printer<'a> {
         Printer {
@@ -147,7 +184,7 @@ impl<'a> Printer<'a> {
     ///
     /// ```text
     /// <timestamp> <level> <args>
-    /// └─ <module> @ <file>:<line>
+    /// └─ <module> @ <file>:<line>[:<column>]
     /// ```
     pub fn print_colored<W: io::Write>(&self, sink: &mut W) -> io::Result<()> {
         writeln!(
@@ -256,10 +293,22 @@ fn color_diff(text: String) -> String {
 pub fn init_logger(
     always_include_location: bool,
     should_log: impl Fn(&log::Metadata) -> bool + Sync + Send + 'static,
+) {
+    init_logger_with_sink(always_include_location, io::stdout(), should_log)
+}
+
+/// Like [`init_logger`] but prints defmt frames to `sink` instead of stdout.
+///
+/// `sink` is flushed by `log::logger().flush()`.
+pub fn init_logger_with_sink(
+    always_include_location: bool,
+    sink: impl Write + Send + 'static,
+    should_log: impl Fn(&log::Metadata) -> bool + Sync + Send + 'static,
 ) {
     log::set_boxed_logger(Box::new(Logger {
         always_include_location,
         should_log: Box::new(should_log),
+        sink: Mutex::new(Box::new(sink)),
         timing_align: AtomicUsize::new(0),
     }))
     .unwrap();
@@ -271,6 +320,9 @@ struct Logger {
 
     should_log: Box<dyn Fn(&log::Metadata) -> bool + Sync + Send>,
 
+    /// Where defmt frames go
+    sink: Mutex<Box<dyn Write + Send>>,
+
     /// Number of characters used by the timestamp. This may increase over time and is used to align
     /// messages.
     timing_align: AtomicUsize,
@@ -288,9 +340,9 @@ impl Log for Logger {
 
         match DefmtRecord::new(record) {
             Some(defmt) => {
-                // defmt goes to stdout, since it's the primary output produced by this tool.
-                let stdout = io::stdout();
-                let mut sink = stdout.lock();
+                // defmt goes to stdout (or the configured sink), since it's the primary output
+                // produced by this tool.
+                let mut sink = self.sink.lock().unwrap();
 
                 let len = defmt.timestamp().len();
                 self.timing_align.fetch_max(len, Ordering::Relaxed);
@@ -300,7 +352,7 @@ impl Log for Logger {
                     .printer()
                     .include_location(true) // always include location for defmt output
                     .min_timestamp_width(min_timestamp_width)
-                    .print_colored(&mut sink)
+                    .print_colored(&mut *sink)
                     .ok();
             }
             None => {
@@ -330,7 +382,9 @@ impl Log for Logger {
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.sink.lock().unwrap().flush().ok();
+    }
 }
 
 fn color_for_log_level(level: Level) -> Color {
@@ -350,9 +404,20 @@ fn print_location<W: io::Write>(sink: &mut W, record: &Record) -> io::Result<()>
         let mut loc = file.to_string();
         if let Some(line) = record.line() {
             loc.push_str(&format!(":{}", line));
+            if let Some(column) = column(record) {
+                loc.push_str(&format!(":{}", column));
+            }
         }
         writeln!(sink, "{}", format!("└─ {} @ {}", mod_path, loc).dimmed())?;
     }
 
     Ok(())
 }
+
+fn column(record: &Record) -> Option<u32> {
+    record
+        .key_values()
+        .get(COLUMN_KEY.into())
+        .and_then(|column| column.to_u64())
+        .map(|column| column as u32)
+}
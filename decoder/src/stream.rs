@@ -0,0 +1,284 @@
+use std::{iter, slice};
+
+use crate::{DecodeError, Frame, Table};
+
+/// Incrementally decodes frames from a stream of bytes
+///
+/// Data is fed in chunks of any size with [`FrameStreamDecoder::push`]; frames are taken out with
+/// [`FrameStreamDecoder::decode`] (or [`FrameStreamDecoder::frames`]) as soon as they are complete.
+/// Bytes that don't form a complete frame yet stay buffered until more data is pushed.
+///
+/// A stream that carries the logs of several firmware images (e.g. a bootloader and an
+/// application) can be decoded with several tables, see [`FrameStreamDecoder::with_tables`].
+pub struct FrameStreamDecoder<'t, T = Table> {
+    tables: &'t [T],
+    buffer: Vec<u8>,
+    /// Start of the data in `buffer` that has not been decoded yet
+    start: usize,
+    /// Position of `buffer[start]` in the stream
+    position: usize,
+}
+
+/// A run of bytes that [`FrameStreamDecoder::decode_with`] discarded to resynchronize
+#[derive(Debug)]
+pub struct Skipped<'b> {
+    /// The discarded bytes
+    pub bytes: &'b [u8],
+    /// Position of the first discarded byte in the stream, counted from the first pushed byte
+    pub position: usize,
+    /// Why the first of the `bytes` could not be decoded
+    pub error: DecodeError,
+}
+
+impl<'t> FrameStreamDecoder<'t> {
+    pub fn new(table: &'t Table) -> Self {
+        Self::with_tables(slice::from_ref(table))
+    }
+}
+
+impl<'t, T: AsRef<Table>> FrameStreamDecoder<'t, T> {
+    /// Creates a decoder that decodes each frame with the first of `tables` that accepts it.
+    ///
+    /// `T` is anything that holds a table, so that callers can tell which of their items decoded
+    /// a frame (see [`FrameStreamDecoder::decode_with`]).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `tables` is empty.
+    pub fn with_tables(tables: &'t [T]) -> Self {
+        assert!(!tables.is_empty(), "at least one table is needed");
+
+        Self {
+            tables,
+            buffer: Vec::new(),
+            start: 0,
+            position: 0,
+        }
+    }
+
+    /// Appends `bytes` to the data that is waiting to be decoded.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Decodes the next frame.
+    ///
+    /// Returns `None` if more data is needed to complete the next frame.
+    ///
+    /// On a [`DecodeError::Malformed`] error, the first buffered byte is discarded so that calling
+    /// `decode` again resumes decoding at the next byte. Callers that would rather give up can
+    /// inspect the offending data with [`FrameStreamDecoder::buffered`] first.
+    pub fn decode(&mut self) -> Option<Result<Frame<'t>, DecodeError>> {
+        match self.decode_at(0) {
+            Ok((frame, consumed, _)) => {
+                self.consume(consumed);
+                Some(Ok(frame))
+            }
+            Err(DecodeError::UnexpectedEof) => None,
+            Err(e @ DecodeError::Malformed { .. }) => {
+                self.consume(1);
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Decodes the next frame, skipping malformed data.
+    ///
+    /// Returns `None` if more data is needed to complete the next frame.
+    ///
+    /// Unlike [`FrameStreamDecoder::decode`], this doesn't stop at malformed data: bytes are
+    /// discarded until decoding resynchronizes and the discarded run is passed to `on_raw`, right
+    /// before the frame that follows it is returned. A run that reaches the end of the buffered
+    /// data is passed to `on_raw` before returning `None`, so a run that spans several
+    /// [`FrameStreamDecoder::push`]es is reported in several pieces. This lets callers route data
+    /// that isn't defmt (e.g. other diagnostics on the same link) elsewhere instead of losing it.
+    pub fn decode_skipping(&mut self, mut on_raw: impl FnMut(&[u8])) -> Option<Frame<'t>> {
+        self.decode_with(|skipped| on_raw(skipped.bytes))
+            .map(|(frame, _)| frame)
+    }
+
+    /// Like [`FrameStreamDecoder::decode_skipping`], but with details: each discarded run is
+    /// passed to `on_skip` together with its position and the error that started it, and the
+    /// frame is returned together with the item of `tables` that decoded it.
+    pub fn decode_with(
+        &mut self,
+        mut on_skip: impl FnMut(Skipped<'_>),
+    ) -> Option<(Frame<'t>, &'t T)> {
+        let mut skipped = 0;
+        let mut error = None;
+        let result = loop {
+            match self.decode_at(skipped) {
+                Ok((frame, consumed, table)) => break Some((frame, consumed, table)),
+                Err(DecodeError::UnexpectedEof) => break None,
+                Err(e @ DecodeError::Malformed { .. }) => {
+                    error.get_or_insert(e);
+                    skipped += 1;
+                }
+            }
+        };
+
+        if let Some(error) = error {
+            on_skip(Skipped {
+                bytes: &self.buffered()[..skipped],
+                position: self.position,
+                error,
+            });
+            self.consume(skipped);
+        }
+
+        let (frame, consumed, table) = result?;
+        self.consume(consumed);
+        Some((frame, table))
+    }
+
+    /// Returns an iterator over all frames that can be decoded from the data pushed so far.
+    ///
+    /// See [`FrameStreamDecoder::decode`] for how malformed data is handled.
+    pub fn frames(&mut self) -> impl Iterator<Item = Result<Frame<'t>, DecodeError>> + '_ {
+        iter::from_fn(move || self.decode())
+    }
+
+    /// Returns the data that has been pushed but not decoded yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Returns the position in the stream of the first byte that has not been decoded yet.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Decodes a frame at `offset` into the buffered data with the first table that accepts it.
+    ///
+    /// If no table accepts the frame, but some need more data, the result is `UnexpectedEof`.
+    fn decode_at(&self, offset: usize) -> Result<(Frame<'t>, usize, &'t T), DecodeError> {
+        let bytes = &self.buffered()[offset..];
+        let mut error = None;
+        for table in self.tables {
+            match table.as_ref().decode(bytes) {
+                Ok((frame, consumed)) => return Ok((frame, consumed, table)),
+                Err(DecodeError::UnexpectedEof) => error = Some(DecodeError::UnexpectedEof),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        // NOTE(unwrap) `with_tables` checks that there is at least one table
+        Err(error.unwrap())
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.start += n;
+        self.position += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{TableEntry, Tag};
+
+    fn table() -> Table {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            1,
+            TableEntry::new_without_symbol(Tag::Info, "x={=u16}".to_owned()),
+        );
+        Table::new(entries)
+    }
+
+    fn messages(decoder: &mut FrameStreamDecoder) -> Vec<Result<String, DecodeError>> {
+        decoder
+            .frames()
+            .map(|frame| frame.map(|frame| frame.display_message().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn frames_split_across_chunks() {
+        let table = table();
+        let mut decoder = table.new_stream_decoder();
+
+        decoder.push(&[1, 42, 0, 1]);
+        assert_eq!(messages(&mut decoder), [Ok("x=42".to_owned())]);
+        assert_eq!(decoder.buffered(), [1]);
+
+        decoder.push(&[7]);
+        assert_eq!(messages(&mut decoder), []);
+
+        decoder.push(&[0]);
+        assert_eq!(messages(&mut decoder), [Ok("x=7".to_owned())]);
+        assert!(decoder.buffered().is_empty());
+        assert_eq!(decoder.position(), 6);
+    }
+
+    #[test]
+    fn decode_skipping() {
+        let table = table();
+        let mut decoder = table.new_stream_decoder();
+        let mut raw = vec![];
+
+        // index 3 is not in the table
+        decoder.push(&[3, 3, 1, 5, 0, 3]);
+        let frame = decoder.decode_skipping(|bytes| raw.push(bytes.to_vec()));
+        assert_eq!(frame.unwrap().display_message().to_string(), "x=5");
+        assert_eq!(raw, [[3, 3]]);
+
+        assert!(decoder
+            .decode_skipping(|bytes| raw.push(bytes.to_vec()))
+            .is_none());
+        assert_eq!(raw, [vec![3, 3], vec![3]]);
+        assert!(decoder.buffered().is_empty());
+    }
+
+    #[test]
+    fn resync_after_malformed_data() {
+        let table = table();
+        let mut decoder = FrameStreamDecoder::new(&table);
+
+        // index 3 is not in the table
+        decoder.push(&[3, 1, 5, 0]);
+        let messages = messages(&mut decoder);
+        assert!(matches!(messages[0], Err(DecodeError::Malformed { .. })));
+        assert_eq!(messages[1..], [Ok("x=5".to_owned())]);
+    }
+
+    #[test]
+    fn decode_with_multiple_tables() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            2,
+            TableEntry::new_without_symbol(Tag::Info, "bootloader".to_owned()),
+        );
+        let tables = [table(), Table::new(entries)];
+        let mut decoder = FrameStreamDecoder::with_tables(&tables);
+
+        // index 3 is in neither table
+        decoder.push(&[2, 3, 3, 1, 5, 0]);
+        let mut skipped = vec![];
+        let mut decoded = vec![];
+        while let Some((frame, table)) = decoder.decode_with(|run| {
+            skipped.push((run.bytes.to_vec(), run.position, run.error));
+        }) {
+            let table = tables.iter().position(|t| std::ptr::eq(t, table));
+            decoded.push((frame.display_message().to_string(), table));
+        }
+
+        assert_eq!(
+            decoded,
+            [
+                ("bootloader".to_owned(), Some(1)),
+                ("x=5".to_owned(), Some(0))
+            ]
+        );
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, [3, 3]);
+        assert_eq!(skipped[0].1, 1);
+        assert!(matches!(skipped[0].2, DecodeError::Malformed { .. }));
+    }
+}
@@ -0,0 +1,374 @@
+//! The decoding loop of `defmt-print`, for tools that want to embed it instead of running the
+//! binary
+//!
+//! [`run_decoder`] decodes everything read from a reader; [`decode_buffered`] and
+//! [`decode_frames`] are the building blocks for tools that manage reading (and the buffer)
+//! themselves.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use defmt_decoder::{
+    DecodeError, Frame, FrameStreamDecoder, Location, Locations, StringEntry, Table, TableEntry,
+    Tag,
+};
+
+/// Size of the buffer that data is read into
+pub const READ_BUFFER_SIZE: usize = 1024;
+const MALFORMED_PREVIEW_LEN: usize = 8;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses (`true`) or enables the `(HOST)` diagnostics that are printed to stderr
+///
+/// Decoded frames are not affected; they go through the `log` sink.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Returns `true` if `(HOST)` diagnostics are suppressed, see [`set_quiet`]
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Like `eprintln!`, for `(HOST)` diagnostics; prints nothing after `set_quiet(true)`
+#[macro_export]
+macro_rules! host_eprintln {
+    ($($arg:tt)*) => {
+        if !$crate::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// The defmt table and (if complete) location info of one ELF file
+pub struct LoadedElf {
+    pub table: Table,
+    pub locs: Option<Locations>,
+}
+
+impl AsRef<Table> for LoadedElf {
+    fn as_ref(&self) -> &Table {
+        &self.table
+    }
+}
+
+/// Decodes everything read from `reader` until EOF and passes each frame, together with its
+/// location (if known), to `on_frame`.
+///
+/// Each frame is decoded with the table of the first of `elfs` that accepts it. Data that can't
+/// be decoded (e.g. non-defmt bytes interleaved by the firmware) is skipped and passed to
+/// `on_raw`; see [`decode_frames`] for when.
+///
+/// # Panics
+///
+/// This function panics if `elfs` is empty.
+pub fn run_decoder(
+    mut reader: impl Read,
+    elfs: &[LoadedElf],
+    mut on_raw: impl FnMut(&[u8]),
+    mut on_frame: impl FnMut(&Frame, Option<&Location>),
+) -> anyhow::Result<()> {
+    assert!(
+        !elfs.is_empty(),
+        "at least one ELF is needed to decode frames"
+    );
+
+    let mut buf = [0; READ_BUFFER_SIZE];
+    // one decoder for the whole stream, so that only newly read bytes are copied into its buffer
+    let mut decoder = FrameStreamDecoder::with_tables(elfs);
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+
+        decoder.push(&buf[..n]);
+        decode_buffered(&mut decoder, 0, false, &mut on_raw, |frame, elf| {
+            let loc = elf.locs.as_ref().and_then(|locs| locs.get(&frame.index()));
+            on_frame(&frame, loc);
+            Ok(())
+        })?;
+    }
+}
+
+/// Decodes all complete frames at the start of `frames` and passes them, together with the ELF
+/// whose table decoded them, to `on_frame`.
+///
+/// This is a wrapper around [`FrameStreamDecoder::decode_with`] for callers that keep the
+/// undecoded data themselves (e.g. because the ELF files can change between calls); callers that
+/// decode the whole stream with the same ELF files should use [`decode_buffered`], which doesn't
+/// copy the undecoded data back and forth. Each frame is
+/// decoded with the first table that accepts it. Decoded frames are removed from `frames`; an
+/// incomplete frame at the end is left in place. Malformed data is discarded until decoding
+/// succeeds again, unless `strict` is set, in which case it is reported as an error. `offset` is
+/// the position of `frames` in the stream and is only used in diagnostics.
+///
+/// Each run of discarded bytes is passed to `on_raw` once decoding has resynchronized, right
+/// before the frame that follows it is passed to `on_frame`. A run that reaches the end of
+/// `frames` is passed to `on_raw` before returning, so a run that spans several calls is reported
+/// in several pieces. Nothing is passed to `on_raw` in `strict` mode.
+///
+/// Returns the number of discarded bytes.
+pub fn decode_frames<'t>(
+    elfs: &'t [LoadedElf],
+    frames: &mut VecDeque<u8>,
+    offset: usize,
+    strict: bool,
+    on_raw: impl FnMut(&[u8]),
+    on_frame: impl FnMut(Frame<'t>, &'t LoadedElf) -> anyhow::Result<()>,
+) -> anyhow::Result<usize> {
+    let mut decoder = FrameStreamDecoder::with_tables(elfs);
+    decoder.push(frames.make_contiguous());
+    frames.clear();
+
+    let result = decode_buffered(&mut decoder, offset, strict, on_raw, on_frame);
+    frames.extend(decoder.buffered());
+    result
+}
+
+/// Like [`decode_frames`], but decodes the data buffered in `decoder`, for callers that keep one
+/// [`FrameStreamDecoder`] for the whole stream and [`FrameStreamDecoder::push`] each chunk they
+/// read into it.
+///
+/// `offset` is added to the stream positions that `decoder` reports, in diagnostics.
+pub fn decode_buffered<'t>(
+    decoder: &mut FrameStreamDecoder<'t, LoadedElf>,
+    offset: usize,
+    strict: bool,
+    mut on_raw: impl FnMut(&[u8]),
+    mut on_frame: impl FnMut(Frame<'t>, &'t LoadedElf) -> anyhow::Result<()>,
+) -> anyhow::Result<usize> {
+    let mut total_skipped = 0;
+    loop {
+        let mut error = None;
+        let decoded = decoder.decode_with(|skipped| {
+            total_skipped += skipped.bytes.len();
+            if strict {
+                log::error!("failed to decode defmt data: {:x?}", skipped.bytes);
+                error = Some(skipped.error);
+                return;
+            }
+
+            host_eprintln!(
+                "(HOST) {}; skipped {} bytes of malformed data",
+                malformed_context(&skipped.error, offset + skipped.position, skipped.bytes),
+                skipped.bytes.len()
+            );
+            on_raw(skipped.bytes);
+        });
+
+        if let Some(e) = error {
+            return Err(e.into());
+        }
+        match decoded {
+            Some((frame, elf)) => on_frame(frame, elf)?,
+            None => return Ok(total_skipped),
+        }
+    }
+}
+
+/// Describes malformed data at stream position `offset`, with a preview of the first few `bytes`
+fn malformed_context(e: &DecodeError, offset: usize, bytes: &[u8]) -> String {
+    let preview = &bytes[..bytes.len().min(MALFORMED_PREVIEW_LEN)];
+    let ellipsis = if bytes.len() > preview.len() {
+        " .."
+    } else {
+        ""
+    };
+    format!(
+        "{} (stream offset {}: {:02x?}{})",
+        e, offset, preview, ellipsis
+    )
+}
+
+/// A table with one entry per log level, using a `{=u8:µs}` timestamp.
+///
+/// Used by `--self-test`.
+pub fn self_test_table() -> Table {
+    let entries = vec![
+        (Tag::Trace, "trace: {=u8}"),
+        (Tag::Debug, "debug: {=i16}"),
+        (Tag::Info, "info: {=f32} {=bool}"),
+        (Tag::Warn, "warn: {=str}"),
+        (Tag::Error, "error: {=[u8]:x}"),
+    ];
+    let entries = entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, (tag, format))| {
+            let string = StringEntry::new(tag, format.to_string());
+            (index, TableEntry::new(string, "<self-test>".to_string()))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut table = Table::new(entries);
+    table.set_timestamp_entry(TableEntry::new(
+        StringEntry::new(Tag::Timestamp, "{=u8:µs}".to_string()),
+        "<self-test>".to_string(),
+    ));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resync_after_malformed_frame() {
+        let elfs = [LoadedElf {
+            table: self_test_table(),
+            locs: None,
+        }];
+        // valid frame, garbage (unknown index 0x7f), valid frame
+        let mut frames = VecDeque::from(vec![0, 1, 42, 0x7f, 0x7e, 1, 2, 0xd6, 0xff]);
+
+        let mut decoded = vec![];
+        let mut raw = vec![];
+        let on_raw = |bytes: &[u8]| raw.push(bytes.to_vec());
+        let skipped = decode_frames(&elfs, &mut frames, 0, false, on_raw, |frame, _| {
+            decoded.push(frame.display_message().to_string());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(skipped, 2);
+        assert_eq!(decoded, ["trace: 42", "debug: -42"]);
+        assert_eq!(raw, [[0x7f, 0x7e]]);
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn malformed_context() {
+        let e = DecodeError::Malformed {
+            offset: 0,
+            context: "unknown log string index",
+        };
+        let message = super::malformed_context(&e, 3, &[0x7f, 0x7e, 1, 2, 0xd6, 0xff, 0, 1, 42]);
+        assert!(message.ends_with("(stream offset 3: [7f, 7e, 01, 02, d6, ff, 00, 01] ..)"));
+    }
+
+    #[test]
+    fn strict_aborts_on_malformed_frame() {
+        let elfs = [LoadedElf {
+            table: self_test_table(),
+            locs: None,
+        }];
+        let mut frames = VecDeque::from(vec![0, 1, 42, 0x7f, 1, 2, 0xd6, 0xff]);
+
+        let mut decoded = 0;
+        let res = decode_frames(
+            &elfs,
+            &mut frames,
+            0,
+            true,
+            |_| {},
+            |_, _| {
+                decoded += 1;
+                Ok(())
+            },
+        );
+
+        assert!(res.is_err());
+        assert_eq!(decoded, 1);
+    }
+
+    #[test]
+    fn decode_with_multiple_tables() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            5,
+            TableEntry::new(
+                StringEntry::new(Tag::Info, "bootloader".to_string()),
+                "<test>".to_string(),
+            ),
+        );
+        let elfs = [
+            LoadedElf {
+                table: self_test_table(),
+                locs: None,
+            },
+            LoadedElf {
+                table: Table::new(entries),
+                locs: None,
+            },
+        ];
+        let mut frames = VecDeque::from(vec![5, 0, 1, 42]);
+
+        let mut decoded = vec![];
+        let skipped = decode_frames(
+            &elfs,
+            &mut frames,
+            0,
+            true,
+            |_| {},
+            |frame, _| {
+                decoded.push(frame.display_message().to_string());
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(skipped, 0);
+        assert_eq!(decoded, ["bootloader", "trace: 42"]);
+    }
+
+    #[test]
+    fn run_decoder() {
+        let elfs = [LoadedElf {
+            table: self_test_table(),
+            locs: None,
+        }];
+        let bytes: &[u8] = &[0, 1, 42, 0x7f, 1, 2, 0xd6, 0xff, 0];
+
+        let mut decoded = vec![];
+        let mut raw = vec![];
+        let on_raw = |bytes: &[u8]| raw.push(bytes.to_vec());
+        super::run_decoder(bytes, &elfs, on_raw, |frame, loc| {
+            assert!(loc.is_none());
+            decoded.push(frame.display_message().to_string());
+        })
+        .unwrap();
+
+        assert_eq!(decoded, ["trace: 42", "debug: -42"]);
+        assert_eq!(raw, [[0x7f]]);
+    }
+
+    #[test]
+    fn run_decoder_with_frames_split_across_reads() {
+        /// Returns the data one byte per `read`
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let elfs = [LoadedElf {
+            table: self_test_table(),
+            locs: None,
+        }];
+        let reader = Trickle(&[0, 1, 42, 0x7f, 0x7e, 1, 2, 0xd6, 0xff, 0]);
+
+        let mut decoded = vec![];
+        let mut raw = vec![];
+        let on_raw = |bytes: &[u8]| raw.extend_from_slice(bytes);
+        super::run_decoder(reader, &elfs, on_raw, |frame, _| {
+            decoded.push(frame.display_message().to_string());
+        })
+        .unwrap();
+
+        assert_eq!(decoded, ["trace: 42", "debug: -42"]);
+        assert_eq!(raw, [0x7f, 0x7e]);
+    }
+}
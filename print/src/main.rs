@@ -1,29 +1,415 @@
 use std::{
-    env, fs,
-    io::{self, Read},
-    path::PathBuf,
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    hash::Hasher,
+    io::{self, BufWriter, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::anyhow;
-use defmt_decoder::Table;
+use anyhow::{anyhow, bail, ensure};
+use chrono::{DateTime, Local, SecondsFormat, TimeZone};
+use defmt_decoder::{Frame, Locations, LocationsOptions, Table};
+use defmt_print::{decode_frames, host_eprintln, self_test_table, LoadedElf, READ_BUFFER_SIZE};
+use flate2::read::GzDecoder;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use pretty::{pretty_message, PRETTY_WIDTH};
+use record::{Header, Recorder};
+use regex::Regex;
+use seahash::SeaHasher;
 use structopt::StructOpt;
 
+mod pretty;
+mod record;
+#[cfg(feature = "usb")]
+mod usb;
+
 /// Prints defmt-encoded logs to stdout
 #[derive(StructOpt)]
 #[structopt(name = "defmt-print")]
 struct Opts {
-    #[structopt(short, parse(from_os_str), required_unless_one(&["version"]))]
-    elf: Option<PathBuf>,
+    /// ELF file(s) of the firmware; can be repeated, e.g. for a bootloader and an application
+    /// that log over the same link. `-` reads the ELF from stdin, in which case the log data has
+    /// to come from `--file`, `--tcp` or `--usb`
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        required_unless_one(&["version", "self-test", "raw-hex", "table"])
+    )]
+    elf: Vec<PathBuf>,
+
+    /// Table file (see `--export-table`) to decode with, instead of or in addition to ELF files
+    /// (can be repeated)
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    table: Vec<PathBuf>,
+
+    /// Don't decode anything; write the table and location info of the ELF file to this file, so
+    /// that logs can be decoded (with `--table`) without the ELF file
+    #[structopt(long, parse(from_os_str), requires = "elf")]
+    export_table: Option<PathBuf>,
+
+    /// Cache parsed tables in this directory to speed up start-up for previously seen ELF files
+    /// (requires the `cache` feature)
+    #[structopt(long, parse(try_from_os_str = parse_cache_dir))]
+    cache_dir: Option<PathBuf>,
+
+    /// Reload the defmt table whenever the ELF file changes (e.g. after re-flashing)
+    #[structopt(long)]
+    watch_elf: bool,
+
+    /// Split DWARF (`.dwo`) file with the debug info of the ELF (can be repeated); needed to show
+    /// locations if the firmware was built with `-Csplit-debuginfo=unpacked`
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    dwo: Vec<PathBuf>,
+
+    /// Packed split DWARF (`.dwp`) file with the debug info of the ELF; needed to show locations
+    /// if the firmware was built with `-Csplit-debuginfo=packed`
+    #[structopt(long, parse(from_os_str))]
+    dwp: Option<PathBuf>,
+
+    /// Linker map file to recover (file-less) location info from if the ELF has no debug info
+    #[structopt(long, parse(from_os_str))]
+    map: Option<PathBuf>,
+
+    /// Rewrite source paths starting with `old` to start with `new` instead (can be repeated)
+    #[structopt(long, value_name = "old=new", number_of_values = 1, parse(try_from_str = parse_substitution))]
+    substitute_path: Vec<(PathBuf, PathBuf)>,
+
+    /// Resolve relative source paths (e.g. from builds with a relative compilation directory)
+    /// against this directory
+    #[structopt(long, parse(from_os_str))]
+    source_root: Option<PathBuf>,
+
+    /// Output format: `text` or `json` (one object per line; requires the `json` feature)
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// Only output these fields, in this order (comma-separated list of `timestamp`, `level`,
+    /// `file`, `line`, `column`, `module`, `crate` and `message`); by default everything is output
+    #[structopt(long, use_delimiter = true)]
+    fields: Vec<Field>,
+
+    /// When to color the output: `auto` (if stdout is a terminal and `NO_COLOR` is not set),
+    /// `always` or `never`; output to a file is never colored
+    #[structopt(long, default_value = "auto")]
+    color: ColorChoice,
+
+    /// Spread long messages with nested data (e.g. `{:?}` of structs) across multiple indented
+    /// lines, similar to `{:#?}`; has no effect on JSON output
+    #[structopt(long)]
+    pretty: bool,
+
+    /// Prefix each frame with the host's local time at which it was decoded (ISO-8601)
+    #[structopt(long)]
+    host_timestamp: bool,
+
+    /// Exit with an error as soon as the firmware panics
+    #[structopt(long)]
+    stop_on_panic: bool,
+
+    /// Only show frames at or above this level (`trace`, `debug`, `info`, `warn`, `error` or `off`)
+    #[structopt(long, default_value = "trace")]
+    log_level: log::LevelFilter,
+
+    /// Only show frames whose timestamp is at least this value (in the units of the firmware's
+    /// timestamp, e.g. microseconds for `{=u64:us}`)
+    #[structopt(long, value_name = "T")]
+    since: Option<u128>,
+
+    /// Only show frames whose timestamp is at most this value (see `--since`)
+    #[structopt(long, value_name = "T")]
+    until: Option<u128>,
+
+    /// With `--since` or `--until`, also hide frames that have no timestamp
+    #[structopt(long)]
+    strict_time: bool,
+
+    /// Only show frames whose module path (e.g. `mydriver::radio`) matches this regular expression
+    #[structopt(long, value_name = "REGEX")]
+    module_filter: Option<Regex>,
+
+    /// Only show frames from crates.io crates whose name matches this regular expression
+    #[structopt(long, value_name = "REGEX")]
+    crate_filter: Option<Regex>,
+
+    /// With `--module-filter` or `--crate-filter`, also show frames whose module or crate is
+    /// unknown (no location info, or not from a crates.io crate, like the firmware itself)
+    #[structopt(long)]
+    include_unknown: bool,
+
+    /// Don't print `(HOST)` diagnostics (warnings, statistics, ...) to stderr; decoded frames are
+    /// still printed, and errors still end the program with a message
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Print decoding statistics to stderr every this many seconds
+    #[structopt(long, value_name = "SECONDS")]
+    stats_interval: Option<u64>,
+
+    /// Don't print consecutive identical messages; report how often they were repeated instead
+    #[structopt(long)]
+    dedup: bool,
+
+    /// Print at most this many frames per second, dropping the rest (panics are always printed);
+    /// the number of dropped frames is part of the statistics
+    #[structopt(long, value_name = "N", parse(try_from_str = parse_max_rate))]
+    max_rate: Option<u32>,
+
+    /// Exit with status 3 if more than this share (0.0 to 1.0) of the recently received data is
+    /// malformed, which usually means that the ELF file doesn't match the running firmware
+    #[structopt(long, value_name = "RATE", parse(try_from_str = parse_threshold))]
+    mismatch_threshold: Option<f64>,
+
+    /// Print data that could not be decoded (e.g. bytes of a side channel on the same link) instead
+    /// of dropping it: as text if it's printable, as hex otherwise
+    #[structopt(long)]
+    passthrough_noise: bool,
+
+    /// Abort on malformed data instead of skipping it
+    #[structopt(long)]
+    strict: bool,
+
+    /// Don't decode anything; print the received bytes as a hex dump (like `hexdump -C`)
+    #[structopt(long)]
+    raw_hex: bool,
+
+    /// Write the decoded output to this file instead of stdout (never colored)
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Also print the decoded output to stdout when `--output` is used
+    #[structopt(long, requires = "output")]
+    tee: bool,
+
+    /// Don't decode anything; list all log statements in the ELF file(s) with their index, level,
+    /// location and format string
+    #[structopt(long)]
+    list: bool,
+
+    /// Decode and print a set of built-in frames, to check that decoding works without a device
+    #[structopt(long)]
+    self_test: bool,
+
+    /// Read defmt data from this file (e.g. a captured log dump) instead of stdin
+    #[structopt(long, parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// If the `--file` is a named pipe (FIFO), wait for the next writer when the current one
+    /// closes it, instead of exiting; like `tail -f`. Regular files still end at EOF
+    #[structopt(long, requires = "file")]
+    follow: bool,
+
+    /// Replay the `--file` at the pace of the device timestamps (taken to be microseconds, as with
+    /// `{=u64:µs}`): the delay between two frames is their timestamp difference multiplied by
+    /// this factor, e.g. `1` for the original speed or `0.5` for twice as fast. `0` prints
+    /// everything right away
+    #[structopt(long, value_name = "FACTOR", requires = "file", parse(try_from_str = parse_replay_speed))]
+    replay_speed: Option<f64>,
+
+    /// Read defmt data from a TCP connection to this address (e.g. a UART-to-TCP bridge)
+    #[structopt(long, value_name = "ADDR:PORT", conflicts_with = "file")]
+    tcp: Option<String>,
+
+    /// Read defmt data from the bulk IN endpoint of the USB device with this vendor and product ID
+    /// (in hex; requires the `usb` feature)
+    #[structopt(
+        long,
+        value_name = "VID:PID",
+        conflicts_with_all(&["file", "tcp"]),
+        parse(try_from_str = parse_usb_id)
+    )]
+    usb: Option<(u16, u16)>,
+
+    /// Also write the received bytes, exactly as received, to this capture file; it starts with a
+    /// header that records the hash of the ELF file(s) and the time of the recording
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Read defmt data from a capture file written by `--record`; warns if it was recorded with
+    /// different ELF file(s)
+    #[structopt(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        conflicts_with_all(&["file", "tcp", "usb"])
+    )]
+    replay: Option<PathBuf>,
+
+    /// Read defmt data from several sources at once, e.g. one per MCU: `NAME=file:PATH`,
+    /// `NAME=serial:DEVICE` (a serial port that has already been configured, e.g. with `stty`),
+    /// `NAME=tcp:ADDR:PORT` or `NAME=usb:VID:PID`. The N-th source is decoded with the N-th
+    /// `--elf`, and its output is prefixed with `[NAME]`
+    #[structopt(
+        long,
+        value_name = "NAME=KIND:ARG",
+        number_of_values = 1,
+        parse(try_from_str = parse_source),
+        conflicts_with_all(&["file", "tcp", "usb", "replay", "record", "raw-hex", "table"])
+    )]
+    source: Vec<Source>,
+
+    /// Byte (in hex) to send after connecting, to signal that we are ready to receive data
+    #[structopt(long, value_name = "HEX", default_value = "63", parse(try_from_str = parse_ready_byte))]
+    ready_byte: u8,
+
+    /// Don't send a ready byte after connecting
+    #[structopt(long, conflicts_with = "ready-byte")]
+    no_ready: bool,
 
     #[structopt(short = "V", long)]
     version: bool,
     // may want to add this later
     // #[structopt(short, long)]
     // verbose: bool,
-    // TODO add file path argument; always use stdin for now
 }
 
-const READ_BUFFER_SIZE: usize = 1024;
+const HEX_DUMP_WIDTH: usize = 16;
+/// Number of recently received bytes that `--mismatch-threshold` looks at
+const MISMATCH_WINDOW: usize = 1024;
+const ELF_MISMATCH_EXIT_CODE: i32 = 3;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+const RELOAD_ATTEMPTS: usize = 5;
+const RELOAD_RETRY_DELAY: Duration = Duration::from_millis(200);
+const DEDUP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// How often `decode_stream` checks for Ctrl-C while no reader sends data
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of chunks of `READ_BUFFER_SIZE` bytes that `spawn_readers` buffers ahead of the decoder
+const READER_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" if cfg!(feature = "json") => Ok(OutputFormat::Json),
+            "json" => Err("`--format json` requires the `json` feature".to_string()),
+            _ => Err(format!("unknown format `{}`; expected `text` or `json`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether the output to stdout should be colored
+    fn should_colorize(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none() && atty::is(atty::Stream::Stdout)
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice `{}`; expected `auto`, `always` or `never`",
+                s
+            )),
+        }
+    }
+}
+
+/// Location of a log statement, as far as it is known, in the form it is output in
+#[derive(Default)]
+struct FrameLocation {
+    file: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+    module_path: Option<String>,
+    crate_name: Option<String>,
+    crate_version: Option<String>,
+}
+
+/// A piece of information about a frame that can be selected with `--fields`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Timestamp,
+    Level,
+    File,
+    Line,
+    Column,
+    Module,
+    Crate,
+    Message,
+}
+
+impl Field {
+    /// Names of the field in the JSON output
+    #[cfg_attr(not(feature = "json"), allow(dead_code))]
+    fn json_keys(self) -> &'static [&'static str] {
+        match self {
+            Field::Timestamp => &["timestamp"],
+            Field::Level => &["level"],
+            Field::File => &["file"],
+            Field::Line => &["line"],
+            Field::Column => &["column"],
+            Field::Module => &["module_path"],
+            Field::Crate => &["crate_name", "crate_version"],
+            Field::Message => &["message", "args"],
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "timestamp" => Field::Timestamp,
+            "level" => Field::Level,
+            "file" => Field::File,
+            "line" => Field::Line,
+            "column" => Field::Column,
+            "module" => Field::Module,
+            "crate" => Field::Crate,
+            "message" => Field::Message,
+            _ => {
+                return Err(format!(
+                    "unknown field `{}`; expected one of timestamp, level, file, line, column, \
+                     module, crate, message",
+                    s
+                ))
+            }
+        })
+    }
+}
 
 fn main() -> anyhow::Result<()> {
     let opts: Opts = Opts::from_args();
@@ -32,83 +418,1938 @@ fn main() -> anyhow::Result<()> {
         return print_version();
     }
 
+    check_stdin_use(&opts)?;
+    defmt_print::set_quiet(opts.quiet);
+
+    let output = Output::new(&opts)?;
+
     let verbose = false;
-    defmt_decoder::log::init_logger(verbose, |metadata| {
-        // We display *all* defmt frames, but nothing else.
+    defmt_decoder::log::init_logger_with_sink(verbose, output.clone(), |metadata| {
+        // We display *all* defmt frames and the warnings of the decoder itself (e.g. about
+        // unusual debug info), but nothing else.
         defmt_decoder::log::is_defmt_frame(metadata)
+            || (metadata.target().starts_with("defmt_decoder")
+                && metadata.level() <= log::Level::Warn)
     });
 
-    let bytes = fs::read(&opts.elf.unwrap())?;
+    let result = if opts.self_test {
+        self_test()
+    } else if let Some(path) = &opts.export_table {
+        load(&opts).and_then(|elfs| export_table(&elfs, path))
+    } else if opts.list {
+        load(&opts).and_then(|elfs| list(&elfs, &mut *output.lock()))
+    } else if opts.raw_hex {
+        // no ELF file is loaded, so its hash is unknown
+        open_source(&opts, 0).and_then(|reader| hex_dump(reader, &output))
+    } else if !opts.source.is_empty() {
+        load(&opts).and_then(|elfs| {
+            let (input, streams) = open_sources(elfs, &opts)?;
+            decode_stream(input, streams, &output, &opts)
+        })
+    } else {
+        load_hashed(&opts).and_then(|(elfs, elf_hash)| {
+            let input = spawn_readers(vec![open_source(&opts, elf_hash)?]);
+            decode_stream(input, vec![Stream::new(None, elfs)], &output, &opts)
+        })
+    };
+
+    // make sure that everything ends up in the `--output` file, also on Ctrl-C and errors
+    output.lock().flush()?;
+
+    if let Err(e) = &result {
+        if e.is::<ElfMismatch>() {
+            eprintln!("Error: {}", e);
+            process::exit(ELF_MISMATCH_EXIT_CODE);
+        }
+    }
+    result
+}
+
+/// Path that stands for stdin in `--elf`
+const STDIN_PATH: &str = "-";
+
+/// Makes sure that at most one thing is read from stdin: the ELF (`--elf -`) or the log data
+fn check_stdin_use(opts: &Opts) -> anyhow::Result<()> {
+    let elfs_from_stdin = opts
+        .elf
+        .iter()
+        .filter(|path| *path == Path::new(STDIN_PATH));
+    match elfs_from_stdin.count() {
+        0 => return Ok(()),
+        1 => {}
+        _ => bail!("only one ELF file can be read from stdin"),
+    }
+
+    if opts.watch_elf {
+        bail!("`--watch-elf` can't watch an ELF file read from stdin");
+    }
+    let needs_log_data = !(opts.self_test || opts.list || opts.export_table.is_some());
+    let log_data_from_stdin = opts.file.is_none()
+        && opts.replay.is_none()
+        && opts.source.is_empty()
+        && opts.tcp.is_none()
+        && opts.usb.is_none();
+    if needs_log_data && log_data_from_stdin {
+        bail!(
+            "both the ELF file (`--elf -`) and the log data would be read from stdin; \
+             read the log data from `--file`, `--tcp` or `--usb` instead"
+        );
+    }
+    Ok(())
+}
+
+/// Destination of the decoded output: stdout, the `--output` file, or both (`--tee`)
+///
+/// Shared by the logger (which prints text frames) and the JSON and hex dump printers.
+#[derive(Clone)]
+struct Output(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl Output {
+    fn new(opts: &Opts) -> anyhow::Result<Self> {
+        // keep escape codes out of the file
+        colored::control::set_override(opts.output.is_none() && opts.color.should_colorize());
+
+        let sink: Box<dyn Write + Send> = match &opts.output {
+            Some(path) => {
+                let file = BufWriter::new(fs::File::create(path)?);
+                if opts.tee {
+                    Box::new(Tee(file, io::stdout()))
+                } else {
+                    Box::new(file)
+                }
+            }
+            None => Box::new(io::stdout()),
+        };
+
+        Ok(Self(Arc::new(Mutex::new(sink))))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Write + Send>> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+/// Writes everything to both `A` and `B`
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
 
-    let table = Table::parse(&bytes)?.ok_or_else(|| anyhow!(".defmt data not found"))?;
-    let locs = table.get_locations(&bytes)?;
+/// Loads all ELF files passed with `-e`.
+fn load(opts: &Opts) -> anyhow::Result<Vec<LoadedElf>> {
+    load_hashed(opts).map(|(loaded, _)| loaded)
+}
 
-    let locs = if table.indices().all(|idx| locs.contains_key(&(idx as u64))) {
-        Some(locs)
+/// Like `load` but also returns a hash of the (decompressed) contents of the ELF files, for
+/// `--record` and `--replay`; the hash is `0` if no ELF file was passed.
+fn load_hashed(opts: &Opts) -> anyhow::Result<(Vec<LoadedElf>, u64)> {
+    let mut hasher = SeaHasher::new();
+    let mut loaded = opts
+        .elf
+        .iter()
+        .map(|path| {
+            let mut load_bytes = |elf: &[u8]| {
+                let elf = decompress(elf)?;
+                hasher.write(&elf);
+                load_from(&elf, opts)
+            };
+            if path == Path::new(STDIN_PATH) {
+                // `parse` needs the whole ELF file
+                let mut elf = vec![];
+                io::stdin().read_to_end(&mut elf)?;
+                load_bytes(&elf)
+            } else if opts.watch_elf {
+                // the ELF file may be rewritten at any time so don't memory-map it
+                load_bytes(&fs::read(path)?)
+            } else {
+                load_bytes(&defmt_decoder::read_elf(path)?)
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let elf_hash = if opts.elf.is_empty() {
+        0
     } else {
-        log::warn!("(BUG) location info is incomplete; it will be omitted from the output");
+        hasher.finish()
+    };
+
+    for path in &opts.table {
+        let file = io::BufReader::new(fs::File::open(path)?);
+        let (table, locs) = defmt_decoder::read_table_file(file)?;
+        warn_on_version_mismatch(&table);
+        let locs = locs.and_then(|locs| complete_locations(&table, locs));
+        loaded.push(LoadedElf { table, locs });
+    }
+
+    Ok((loaded, elf_hash))
+}
+
+/// Writes the table of the (single) ELF file to `path`; used by `--export-table`.
+fn export_table(elfs: &[LoadedElf], path: &Path) -> anyhow::Result<()> {
+    let elf = match elfs {
+        [elf] => elf,
+        _ => bail!("`--export-table` needs exactly one ELF file"),
+    };
+    let file = BufWriter::new(fs::File::create(path)?);
+    defmt_decoder::write_table_file(file, &elf.table, elf.locs.as_ref())
+}
+
+/// Decompresses `bytes` if they are gzip-compressed (e.g. a `.elf.gz` CI artifact); other data is
+/// returned unchanged.
+fn decompress(bytes: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(Cow::Owned(decompressed))
+}
+
+fn load_from(bytes: &[u8], opts: &Opts) -> anyhow::Result<LoadedElf> {
+    let (table, mut locs) = match &opts.cache_dir {
+        Some(cache_dir) => load_cached(cache_dir, bytes, opts)?,
+        None => parse_elf(bytes, opts)?,
+    };
+
+    warn_on_version_mismatch(&table);
+    if locs.is_empty() {
+        if let Some(map) = &opts.map {
+            // no DWARF info; the linker map gives us (some) location info on a best-effort basis
+            locs = table.get_locations_from_map(&fs::read_to_string(map)?);
+        }
+    }
+
+    let locs = if locs.is_empty() && table.indices().next().is_some() && !has_debug_info(bytes)? {
+        // e.g. a stripped release build; that's fine, we just can't show file and line
+        host_eprintln!("(HOST) the ELF file has no debug info; log locations will not be shown");
         None
+    } else {
+        complete_locations(&table, locs)
     };
 
-    let mut buf = [0; READ_BUFFER_SIZE];
-    let mut frames = vec![];
+    Ok(LoadedElf { table, locs })
+}
 
-    let current_dir = env::current_dir()?;
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-    loop {
-        let n = stdin.read(&mut buf)?;
+/// Returns `locs` if every log statement in `table` has a location; otherwise warns about the
+/// missing ones and returns `None`, so that no locations are shown at all
+fn complete_locations(table: &Table, locs: Locations) -> Option<Locations> {
+    let missing = missing_locations(table, &locs);
+    if missing.is_empty() {
+        return Some(locs);
+    }
+
+    host_eprintln!(
+        "(HOST) WARNING (BUG) location info is incomplete; it will be omitted from the output. \
+         {} of {} log statements have no location:",
+        missing.len(),
+        table.indices().count()
+    );
+    for (index, symbol) in missing {
+        host_eprintln!("(HOST)   index {}: {}", index, symbol);
+    }
+    None
+}
 
-        frames.extend_from_slice(&buf[..n]);
+/// Returns the index and raw symbol of every log statement in `table` that has no location
+fn missing_locations<'t>(table: &'t Table, locs: &Locations) -> Vec<(usize, &'t str)> {
+    table
+        .indices()
+        .filter(|index| !locs.contains_key(&(*index as u64)))
+        .map(|index| (index, table.raw_symbol(index).unwrap_or("<unknown>")))
+        .collect()
+}
 
-        loop {
-            match table.decode(&frames) {
-                Ok((frame, consumed)) => {
-                    // NOTE(`[]` indexing) all indices in `table` have already been
-                    // verified to exist in the `locs` map
-                    let loc = locs.as_ref().map(|locs| &locs[&frame.index()]);
-
-                    let (mut file, mut line, mut mod_path) = (None, None, None);
-                    if let Some(loc) = loc {
-                        let relpath = if let Ok(relpath) = loc.file.strip_prefix(&current_dir) {
-                            relpath
-                        } else {
-                            // not relative; use full path
-                            &loc.file
-                        };
-                        file = Some(relpath.display().to_string());
-                        line = Some(loc.line as u32);
-                        mod_path = Some(loc.module.clone());
-                    }
+fn warn_on_version_mismatch(table: &Table) {
+    if let Some(version) = table.version() {
+        if !Table::is_compatible_version(version) {
+            host_eprintln!(
+                "(HOST) WARNING: the firmware uses defmt version {} but this defmt-print supports \
+                 version {}; decoding may fail or produce garbage. Use a defmt-print release that \
+                 matches the firmware's defmt version.",
+                version,
+                defmt_decoder::DEFMT_VERSION
+            );
+        }
+    }
+}
 
-                    // Forward the defmt frame to our logger.
-                    defmt_decoder::log::log_defmt(
-                        &frame,
-                        file.as_deref(),
-                        line,
-                        mod_path.as_deref(),
-                    );
+fn has_debug_info(elf: &[u8]) -> anyhow::Result<bool> {
+    Ok(defmt_decoder::section_names(elf)?
+        .iter()
+        .any(|name| name == ".debug_info"))
+}
+
+/// Like `load` but retries a few times, as the ELF files may still be being written.
+///
+/// Returns `None` if the ELF files could not be loaded.
+fn reload(opts: &Opts) -> Option<Vec<LoadedElf>> {
+    for attempt in 1..=RELOAD_ATTEMPTS {
+        match load(opts) {
+            Ok(loaded) => {
+                host_eprintln!("(HOST) ELF file changed; reloaded the defmt table");
+                return Some(loaded);
+            }
+            Err(e) if attempt == RELOAD_ATTEMPTS => {
+                host_eprintln!(
+                    "(HOST) failed to reload the ELF file: {}; keeping the old table",
+                    e
+                )
+            }
+            Err(_) => thread::sleep(RELOAD_RETRY_DELAY),
+        }
+    }
+
+    None
+}
+
+/// Watches the ELF file for changes (`--watch-elf`)
+struct ElfWatcher {
+    // events are only delivered while the watcher is alive
+    _watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+    path: PathBuf,
+}
+
+impl ElfWatcher {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        let path = path.canonicalize()?;
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, WATCH_DEBOUNCE)?;
+        // watch the parent directory; linkers often replace the file instead of overwriting it,
+        // which would end a watch on the file itself
+        let dir = path.parent().unwrap_or(&path);
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            path,
+        })
+    }
+
+    /// Returns `true` if the ELF file has changed since the last call
+    fn changed(&self) -> bool {
+        // drain all pending events so that one change doesn't trigger multiple reloads
+        self.events
+            .try_iter()
+            .filter(|event| match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => *path == self.path,
+                _ => false,
+            })
+            .count()
+            != 0
+    }
+}
+
+/// Parses the defmt table and the locations of the log statements from `elf`.
+fn parse_elf(elf: &[u8], opts: &Opts) -> anyhow::Result<(Table, Locations)> {
+    // a version mismatch is reported by `load_from` but not fatal; minor versions may still decode
+    let table = match Table::parse_ignore_version(elf)? {
+        Some(table) => table,
+        None => {
+            let sections = defmt_decoder::section_names(elf)?;
+            bail!(
+                "`.defmt` section not found (did you add the defmt linker script?); found sections: {}",
+                sections.join(", ")
+            );
+        }
+    };
+    let locs_opts = LocationsOptions {
+        substitute_paths: opts.substitute_path.clone(),
+        split_dwarf: opts.dwo.clone(),
+        dwp: opts.dwp.clone(),
+        source_root: opts.source_root.clone(),
+    };
+    let (locs, collisions) = table.get_locations_with(elf, &locs_opts)?;
+    for collision in collisions {
+        host_eprintln!("(HOST) WARNING (BUG) {}", collision);
+    }
+    Ok((table, locs))
+}
+
+fn parse_cache_dir(s: &OsStr) -> Result<PathBuf, OsString> {
+    if !cfg!(feature = "cache") {
+        return Err("`--cache-dir` requires the `cache` feature".into());
+    }
+
+    Ok(s.into())
+}
+
+/// Like `parse_elf` but loads the result from `cache_dir` if `elf` has been parsed before.
+///
+/// Cache entries are keyed by a hash of the ELF contents, of the options that affect parsing and of
+/// the versions of defmt-print and of the serialized form of the table.
+#[cfg(feature = "cache")]
+fn load_cached(cache_dir: &Path, elf: &[u8], opts: &Opts) -> anyhow::Result<(Table, Locations)> {
+    use std::hash::Hash;
+
+    let mut hasher = SeaHasher::new();
+    hasher.write(elf);
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.write_u32(defmt_decoder::SERDE_FORMAT_VERSION);
+    opts.substitute_path.hash(&mut hasher);
+    opts.source_root.hash(&mut hasher);
+    for split_dwarf in opts.dwo.iter().chain(&opts.dwp) {
+        hasher.write(&fs::read(split_dwarf)?);
+    }
+    let path = cache_dir.join(format!("{:016x}.json", hasher.finish()));
+
+    if let Ok(cached) = fs::read(&path) {
+        match serde_json::from_slice::<(Table, Locations)>(&cached) {
+            Ok(parsed) => return Ok(parsed),
+            Err(e) => host_eprintln!(
+                "(HOST) ignoring invalid cache entry {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    let parsed = parse_elf(elf, opts)?;
+    fs::create_dir_all(cache_dir)?;
+    fs::write(&path, serde_json::to_vec(&parsed)?)?;
+    Ok(parsed)
+}
+
+#[cfg(not(feature = "cache"))]
+fn load_cached(_: &Path, _: &[u8], _: &Opts) -> anyhow::Result<(Table, Locations)> {
+    unreachable!("`--cache-dir` is rejected when the `cache` feature is disabled")
+}
 
-                    let num_frames = frames.len();
-                    frames.rotate_left(consumed);
-                    frames.truncate(num_frames - consumed);
+/// Parses an `old=new` path substitution
+fn parse_substitution(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() => Ok((old.into(), new.into())),
+        _ => Err(format!("expected `old=new`, found `{}`", s)),
+    }
+}
+
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(threshold),
+        _ => Err(format!("expected a number from 0.0 to 1.0, found `{}`", s)),
+    }
+}
+
+fn parse_replay_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if factor >= 0.0 && factor.is_finite() => Ok(factor),
+        _ => Err(format!("expected a non-negative number, found `{}`", s)),
+    }
+}
+
+fn parse_max_rate(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(rate) if rate != 0 => Ok(rate),
+        _ => Err(format!(
+            "expected a positive number of frames, found `{}`",
+            s
+        )),
+    }
+}
+
+/// Parses a single byte written in hex, with or without `0x` prefix
+fn parse_ready_byte(s: &str) -> Result<u8, String> {
+    let hex = s.trim_start_matches("0x");
+    u8::from_str_radix(hex, 16)
+        .map_err(|_| format!("expected a single byte in hex (e.g. `63`), found `{}`", s))
+}
+
+fn parse_usb_id(s: &str) -> Result<(u16, u16), String> {
+    if !cfg!(feature = "usb") {
+        return Err("`--usb` requires the `usb` feature".to_string());
+    }
+
+    let parse = |id: &str| u16::from_str_radix(id.trim_start_matches("0x"), 16).ok();
+    match s.split_once(':') {
+        Some((vid, pid)) => parse(vid).zip(parse(pid)),
+        None => None,
+    }
+    .ok_or_else(|| {
+        format!(
+            "expected `VID:PID` in hex (e.g. `1209:0001`), found `{}`",
+            s
+        )
+    })
+}
+
+/// Opens the source of defmt data: a `--replay` capture file or a transport, whose data is copied
+/// to the `--record` capture file, if any.
+///
+/// `elf_hash` is the hash of the loaded ELF file(s), as returned by `load_hashed`.
+fn open_source(opts: &Opts, elf_hash: u64) -> anyhow::Result<Box<dyn Read + Send>> {
+    let reader = match &opts.replay {
+        Some(path) => open_replay(path, elf_hash)?,
+        None => open_transport(opts)?,
+    };
+
+    match &opts.record {
+        Some(path) => {
+            let mut capture = fs::File::create(path)?;
+            Header {
+                elf_hash,
+                recorded_at: Local::now().timestamp_millis(),
+            }
+            .write(&mut capture)?;
+            Ok(Box::new(Recorder { reader, capture }))
+        }
+        None => Ok(reader),
+    }
+}
+
+/// Opens a capture file written by `--record`, checking that it was recorded with the same ELF
+/// file(s).
+fn open_replay(path: &Path, elf_hash: u64) -> anyhow::Result<Box<dyn Read + Send>> {
+    let mut file = fs::File::open(path)?;
+    let header = Header::read(&mut file)
+        .map_err(|e| anyhow!("{}: invalid capture file: {}", path.display(), e))?;
+
+    if let Some(recorded_at) = Local.timestamp_millis_opt(header.recorded_at).single() {
+        host_eprintln!(
+            "(HOST) replaying a capture recorded at {}",
+            host_timestamp(recorded_at)
+        );
+    }
+    if header.elf_hash != 0 && elf_hash != 0 && header.elf_hash != elf_hash {
+        host_eprintln!(
+            "(HOST) WARNING {} was recorded with different ELF file(s); the output may be garbled",
+            path.display()
+        );
+    }
+
+    Ok(Box::new(file))
+}
+
+/// Opens the transport that defmt data is received from, as selected by `--file`, `--usb` or
+/// `--tcp`; stdin by default.
+fn open_transport(opts: &Opts) -> anyhow::Result<Box<dyn Read + Send>> {
+    let transport = if let Some(path) = &opts.file {
+        Transport::File(path.clone())
+    } else if let Some((vid, pid)) = opts.usb {
+        Transport::Usb(vid, pid)
+    } else if let Some(addr) = &opts.tcp {
+        Transport::Tcp(addr.clone())
+    } else {
+        Transport::Stdin
+    };
+    transport.open(opts)
+}
+
+/// A transport that defmt data is received from
+#[derive(Debug, PartialEq)]
+enum Transport {
+    Stdin,
+    /// A regular file, a named pipe or a serial port
+    File(PathBuf),
+    Tcp(String),
+    Usb(u16, u16),
+}
+
+impl Transport {
+    /// Opens the transport.
+    ///
+    /// This is the one place that needs to change to support a new transport: anything that
+    /// implements `Read` can be fed to `decode_stream`.
+    fn open(&self, opts: &Opts) -> anyhow::Result<Box<dyn Read + Send>> {
+        let ready_byte = if opts.no_ready {
+            None
+        } else {
+            Some(opts.ready_byte)
+        };
+
+        match self {
+            Transport::Stdin => Ok(Box::new(io::stdin())),
+            Transport::File(path) => {
+                let file = fs::File::open(path)?;
+                if opts.follow && is_fifo(&file)? {
+                    return Ok(Box::new(FollowFifo {
+                        path: path.clone(),
+                        file,
+                    }));
                 }
-                Err(defmt_decoder::DecodeError::UnexpectedEof) => break,
-                Err(defmt_decoder::DecodeError::Malformed) => {
-                    log::error!("failed to decode defmt data: {:x?}", frames);
-                    return Err(defmt_decoder::DecodeError::Malformed.into());
+                Ok(Box::new(file))
+            }
+            Transport::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr)?;
+                if let Some(ready_byte) = ready_byte {
+                    // let the other end know that we are ready to receive data
+                    stream.write_all(&[ready_byte])?;
                 }
+                Ok(Box::new(stream))
             }
+            Transport::Usb(vid, pid) => open_usb(*vid, *pid, ready_byte),
         }
     }
 }
 
-/// Report version from Cargo.toml _(e.g. "0.1.4")_ and supported `defmt`-versions.
+/// A named source of defmt data (`--source`)
+#[derive(Debug, PartialEq)]
+struct Source {
+    name: String,
+    transport: Transport,
+}
+
+/// Parses a `NAME=KIND:ARG` source
+fn parse_source(s: &str) -> Result<Source, String> {
+    let (name, transport) = s
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| format!("expected `NAME=KIND:ARG`, found `{}`", s))?;
+    let transport = match transport.split_once(':') {
+        Some(("file", path)) | Some(("serial", path)) if !path.is_empty() => {
+            Transport::File(path.into())
+        }
+        Some(("tcp", addr)) if !addr.is_empty() => Transport::Tcp(addr.to_string()),
+        Some(("usb", id)) => {
+            let (vid, pid) = parse_usb_id(id)?;
+            Transport::Usb(vid, pid)
+        }
+        _ => {
+            return Err(format!(
+                "expected `file:PATH`, `serial:DEVICE`, `tcp:ADDR:PORT` or `usb:VID:PID` after \
+                 `{}=`, found `{}`",
+                name, transport
+            ))
+        }
+    };
+
+    Ok(Source {
+        name: name.to_string(),
+        transport,
+    })
+}
+
+/// Opens all `--source`s and pairs each with its ELF file
+fn open_sources(
+    elfs: Vec<LoadedElf>,
+    opts: &Opts,
+) -> anyhow::Result<(Receiver<Received>, Vec<Stream>)> {
+    ensure!(
+        elfs.len() == opts.source.len(),
+        "each `--source` needs its own `--elf`; got {} sources and {} ELF files",
+        opts.source.len(),
+        elfs.len()
+    );
+
+    let readers = opts
+        .source
+        .iter()
+        .map(|source| source.transport.open(opts))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let streams = opts
+        .source
+        .iter()
+        .zip(elfs)
+        .map(|(source, elf)| Stream::new(Some(source.name.clone()), vec![elf]))
+        .collect();
+    Ok((spawn_readers(readers), streams))
+}
+
+/// Data read by one of the threads of `spawn_readers`, tagged with the index of its reader
+type Received = (usize, io::Result<Vec<u8>>);
+
+/// Reads each of `readers` on its own thread
 ///
-/// Used by `--version` flag.
-#[allow(clippy::unnecessary_wraps)]
-fn print_version() -> anyhow::Result<()> {
-    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    println!("supported defmt version: {}", defmt_decoder::DEFMT_VERSION);
-    Ok(())
+/// The data is sent to the returned channel, tagged with the index of the reader it came from.
+/// Empty data means that the reader has reached EOF; an error also ends the reader's thread.
+///
+/// Reading on a thread keeps `decode_stream` responsive to Ctrl-C while a reader blocks; the
+/// `ctrlc` handler doesn't interrupt a blocking `read`. The channel is bounded, so a reader doesn't
+/// get far ahead of the decoder (e.g. when a `--replay` is paced).
+fn spawn_readers(readers: Vec<Box<dyn Read + Send>>) -> Receiver<Received> {
+    let (sender, receiver) = mpsc::sync_channel(READER_CHANNEL_CAPACITY);
+    for (index, mut reader) in readers.into_iter().enumerate() {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let mut buf = [0; READ_BUFFER_SIZE];
+            loop {
+                let data = match reader.read(&mut buf) {
+                    Ok(n) => Ok(buf[..n].to_vec()),
+                    // no data within the reader's timeout (e.g. `usb:`); not the end of the input
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(e) => Err(e),
+                };
+                let done = !matches!(&data, Ok(data) if !data.is_empty());
+                if sender.send((index, data)).is_err() || done {
+                    return;
+                }
+            }
+        });
+    }
+
+    receiver
+}
+
+#[cfg(unix)]
+fn is_fifo(file: &fs::File) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    Ok(file.metadata()?.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_: &fs::File) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Reads from a named pipe, re-opening it whenever its writer closes it (`--follow`)
+///
+/// Re-opening blocks until the next writer opens the pipe. Data of a frame that was cut off stays
+/// buffered in the decoder and is completed (or skipped as malformed) by the next writer's data.
+struct FollowFifo {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Read for FollowFifo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n != 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            host_eprintln!("(HOST) the writer closed the FIFO; waiting for it to be reopened");
+            self.file = fs::File::open(&self.path)?;
+        }
+    }
+}
+
+#[cfg(feature = "usb")]
+fn open_usb(vid: u16, pid: u16, ready_byte: Option<u8>) -> anyhow::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(usb::UsbReader::open(vid, pid, ready_byte)?))
+}
+
+#[cfg(not(feature = "usb"))]
+fn open_usb(_: u16, _: u16, _: Option<u8>) -> anyhow::Result<Box<dyn Read + Send>> {
+    unreachable!("`--usb` is rejected when the `usb` feature is disabled")
+}
+
+/// A stream of defmt data and the ELF file(s) it's decoded with
+struct Stream {
+    /// Name of the `--source`; prefixed to the output
+    tag: Option<String>,
+    elfs: Vec<LoadedElf>,
+    /// Received data that has not been decoded yet
+    frames: VecDeque<u8>,
+    bytes_read: usize,
+    eof: bool,
+}
+
+impl Stream {
+    fn new(tag: Option<String>, elfs: Vec<LoadedElf>) -> Self {
+        Self {
+            tag,
+            elfs,
+            frames: VecDeque::new(),
+            bytes_read: 0,
+            eof: false,
+        }
+    }
+}
+
+/// Decodes the frames received from `input` and forwards them to the logger.
+///
+/// `input` is fed by `spawn_readers`, with one reader per stream. Returns once all streams reach
+/// EOF, or Ctrl-C was pressed, and all complete frames have been printed.
+fn decode_stream(
+    input: Receiver<Received>,
+    mut streams: Vec<Stream>,
+    output: &Output,
+    opts: &Opts,
+) -> anyhow::Result<()> {
+    let watchers = if opts.watch_elf {
+        opts.elf
+            .iter()
+            .map(|elf| ElfWatcher::new(elf))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        vec![]
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed))?;
+
+    let mut dedup = if opts.dedup { Some(Dedup::new()) } else { None };
+    let mut throttle = opts.max_rate.map(Throttle::new);
+    let mut pacer = opts
+        .replay_speed
+        .filter(|factor| *factor > 0.0)
+        .map(Pacer::new);
+    // destination of `--dedup` reports and `--passthrough-noise`;
+    // JSON consumers expect nothing but JSON in the output
+    let side_sink = || -> Box<dyn Write> {
+        match opts.format {
+            OutputFormat::Text => Box::new(output.clone()),
+            OutputFormat::Json => Box::new(io::stderr()),
+        }
+    };
+
+    let mut mismatch = opts.mismatch_threshold.map(MismatchDetector::new);
+    let mut stats = Stats::default();
+    let stats_interval = opts.stats_interval.map(Duration::from_secs);
+    let mut last_stats = Instant::now();
+    let current_dir = env::current_dir()?;
+    loop {
+        // time out to notice Ctrl-C while all readers are quiet
+        let received = match input.recv_timeout(SOURCE_POLL_INTERVAL) {
+            Ok((index, data)) => Some((index, data?)),
+            Err(RecvTimeoutError::Timeout) => None,
+            // all reader threads are done
+            Err(RecvTimeoutError::Disconnected) => {
+                streams.iter_mut().for_each(|stream| stream.eof = true);
+                None
+            }
+        };
+
+        // NOTE not `any`; all watchers need to be drained
+        if watchers.iter().filter(|watcher| watcher.changed()).count() != 0 {
+            if let Some(new_elfs) = reload(opts) {
+                if let [stream] = &mut streams[..] {
+                    stream.elfs = new_elfs;
+                } else {
+                    // one ELF file per `--source`
+                    for (stream, elf) in streams.iter_mut().zip(new_elfs) {
+                        stream.elfs = vec![elf];
+                    }
+                }
+            }
+        }
+
+        if let Some((index, data)) = received {
+            let Stream {
+                tag,
+                elfs,
+                frames,
+                bytes_read,
+                eof,
+            } = &mut streams[index];
+            *eof = data.is_empty();
+            frames.extend(&data);
+            *bytes_read += data.len();
+            stats.bytes_read += data.len();
+
+            let offset = *bytes_read - frames.len();
+            let buffered = frames.len();
+            let noise = |bytes: &[u8]| {
+                if opts.passthrough_noise {
+                    writeln!(side_sink(), "{}", format_noise(bytes)).ok();
+                }
+            };
+            let skipped = decode_frames(elfs, frames, offset, opts.strict, noise, |frame, elf| {
+                stats.frames += 1;
+
+                if defmt_decoder::log::log_level(frame.level()) > opts.log_level {
+                    return Ok(());
+                }
+
+                if !in_time_window(frame.timestamp(), opts) {
+                    return Ok(());
+                }
+
+                // NOTE(`[]` indexing) all indices in `table` have already been
+                // verified to exist in the `locs` map
+                let loc = elf.locs.as_ref().and_then(|locs| locs.get(&frame.index()));
+
+                if let Some(filter) = &opts.module_filter {
+                    let keep = match loc {
+                        Some(loc) => filter.is_match(&loc.module),
+                        None => opts.include_unknown,
+                    };
+                    if !keep {
+                        return Ok(());
+                    }
+                }
+
+                if let Some(filter) = &opts.crate_filter {
+                    let keep = match loc.and_then(|loc| loc.crate_name.as_ref()) {
+                        Some(crate_name) => filter.is_match(crate_name),
+                        None => opts.include_unknown,
+                    };
+                    if !keep {
+                        return Ok(());
+                    }
+                }
+
+                if let Some(dedup) = &mut dedup {
+                    if dedup.is_repeat(&frame, &mut *side_sink())? {
+                        return Ok(());
+                    }
+                }
+
+                if let Some(throttle) = &mut throttle {
+                    if !frame.is_panic() && !throttle.allow(Instant::now()) {
+                        stats.throttled_frames += 1;
+                        return Ok(());
+                    }
+                }
+
+                let mut location = FrameLocation::default();
+                if let Some(loc) = loc {
+                    // locations recovered from a linker map only know the object file
+                    let file = loc.object.as_ref().unwrap_or(&loc.file);
+                    let relpath = if let Ok(relpath) = file.strip_prefix(&current_dir) {
+                        relpath
+                    } else {
+                        // not relative; use full path
+                        file
+                    };
+                    location = FrameLocation {
+                        file: Some(relpath.display().to_string()),
+                        // `0` means "unknown" (e.g. for locations recovered from a linker map)
+                        line: Some(loc.line as u32).filter(|line| *line != 0),
+                        column: loc.column.map(|column| column as u32),
+                        module_path: Some(loc.module.clone()),
+                        crate_name: loc.crate_name.clone(),
+                        crate_version: loc.crate_version.clone(),
+                    };
+                }
+
+                if let Some(pacer) = &mut pacer {
+                    if let Some(timestamp) = frame.timestamp() {
+                        thread::sleep(pacer.delay(timestamp, Instant::now()));
+                    }
+                }
+
+                let host_timestamp = if opts.host_timestamp {
+                    Some(host_timestamp(Local::now()))
+                } else {
+                    None
+                };
+
+                match opts.format {
+                    OutputFormat::Text => {
+                        if let Some(tag) = tag {
+                            write!(output.lock(), "[{}] ", tag)?;
+                        }
+                        if let Some(host_timestamp) = &host_timestamp {
+                            write!(output.lock(), "{} ", host_timestamp)?;
+                        }
+
+                        let mut message = frame.display_message().to_string();
+                        if opts.pretty {
+                            message = pretty_message(&message, PRETTY_WIDTH);
+                        }
+
+                        if opts.fields.is_empty() {
+                            // Forward the defmt frame to our logger.
+                            defmt_decoder::log::log_defmt_with_message(
+                                &frame,
+                                &message,
+                                location.file.as_deref(),
+                                location.line,
+                                location.column,
+                                location.module_path.as_deref(),
+                            )
+                        } else {
+                            writeln!(
+                                output.lock(),
+                                "{}",
+                                format_fields(&opts.fields, &frame, &message, &location)
+                            )?
+                        }
+                    }
+                    OutputFormat::Json => print_json(
+                        &mut *output.lock(),
+                        &frame,
+                        &location,
+                        host_timestamp.as_deref(),
+                        tag.as_deref(),
+                        &opts.fields,
+                    )?,
+                }
+
+                if frame.is_panic() {
+                    let location = match (&location.file, location.line) {
+                        (Some(file), Some(line)) => format!("{}:{}", file, line),
+                        _ => "<unknown location>".to_string(),
+                    };
+                    let tag = match tag {
+                        Some(tag) => format!("[{}] ", tag),
+                        None => String::new(),
+                    };
+                    host_eprintln!(
+                        "(HOST) {}firmware panic @ {}: {}",
+                        tag,
+                        location,
+                        frame.display_message()
+                    );
+
+                    if opts.stop_on_panic {
+                        bail!("firmware panicked (`--stop-on-panic` is set)");
+                    }
+                }
+
+                Ok(())
+            })?;
+            stats.malformed_bytes += skipped;
+
+            if let Some(detector) = &mut mismatch {
+                if let Some(rate) = detector.record(buffered - frames.len() - skipped, skipped) {
+                    #[cfg(feature = "json")]
+                    if opts.format == OutputFormat::Json {
+                        let event =
+                            serde_json::json!({ "event": "elf_mismatch", "malformed_rate": rate });
+                        writeln!(output.lock(), "{}", event)?;
+                    }
+                    return Err(ElfMismatch { rate }.into());
+                }
+            }
+        }
+
+        if let Some(interval) = stats_interval {
+            if last_stats.elapsed() >= interval {
+                host_eprintln!("(HOST) {}", stats);
+                last_stats = Instant::now();
+            }
+        }
+
+        let eof = streams.iter().all(|stream| stream.eof);
+        let done = eof || stop.load(Ordering::Relaxed);
+        if let Some(dedup) = &mut dedup {
+            if done || dedup.report_due() {
+                dedup.report(&mut *side_sink())?;
+            }
+        }
+
+        if done {
+            for stream in streams
+                .iter()
+                .filter(|stream| eof && !stream.frames.is_empty())
+            {
+                let tag = match &stream.tag {
+                    Some(tag) => format!(" of {}", tag),
+                    None => String::new(),
+                };
+                host_eprintln!(
+                    "(HOST) reached end of input{} with {} bytes of incomplete frame data left",
+                    tag,
+                    stream.frames.len()
+                );
+            }
+            host_eprintln!("(HOST) {}", stats);
+            return Ok(());
+        }
+    }
+}
+
+/// Detects a likely mismatch between the ELF file and the running firmware from the share of
+/// malformed data in the last `MISMATCH_WINDOW` bytes (`--mismatch-threshold`)
+struct MismatchDetector {
+    threshold: f64,
+    /// `(decoded, malformed)` bytes per decoding pass, oldest first
+    window: VecDeque<(usize, usize)>,
+    decoded: usize,
+    malformed: usize,
+}
+
+impl MismatchDetector {
+    fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            window: VecDeque::new(),
+            decoded: 0,
+            malformed: 0,
+        }
+    }
+
+    /// Records the outcome of one decoding pass.
+    ///
+    /// Returns the share of malformed data if the window is full and the share exceeds the
+    /// threshold.
+    fn record(&mut self, decoded: usize, malformed: usize) -> Option<f64> {
+        if decoded + malformed == 0 {
+            return None;
+        }
+
+        self.window.push_back((decoded, malformed));
+        self.decoded += decoded;
+        self.malformed += malformed;
+
+        // drop old passes, as long as the rest still fill the window
+        while let Some(&(decoded, malformed)) = self.window.front() {
+            let total = self.decoded + self.malformed;
+            if total - (decoded + malformed) < MISMATCH_WINDOW {
+                break;
+            }
+            self.window.pop_front();
+            self.decoded -= decoded;
+            self.malformed -= malformed;
+        }
+
+        let total = self.decoded + self.malformed;
+        let rate = self.malformed as f64 / total as f64;
+        if total >= MISMATCH_WINDOW && rate > self.threshold {
+            Some(rate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Error returned by `decode_stream` when `MismatchDetector` triggers; exits with
+/// `ELF_MISMATCH_EXIT_CODE`
+#[derive(Debug)]
+struct ElfMismatch {
+    rate: f64,
+}
+
+impl fmt::Display for ElfMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% of the recent data was malformed; the ELF file likely doesn't match the \
+             firmware",
+            self.rate * 100.
+        )
+    }
+}
+
+impl std::error::Error for ElfMismatch {}
+
+/// Paces the output of a replayed capture by the frames' device timestamps (`--replay-speed`)
+struct Pacer {
+    factor: f64,
+    /// Device timestamp of the previous frame and when it was (to be) printed
+    last: Option<(u128, Instant)>,
+}
+
+impl Pacer {
+    fn new(factor: f64) -> Self {
+        Self { factor, last: None }
+    }
+
+    /// Returns how long to wait, from `now`, before printing the frame with `timestamp`.
+    ///
+    /// The delay is relative to when the previous frame was due, so time spent decoding and
+    /// printing doesn't add up. After the device timestamp goes backwards (e.g. the device was
+    /// reset) pacing restarts from the current frame.
+    fn delay(&mut self, timestamp: u128, now: Instant) -> Duration {
+        let due = match self.last {
+            Some((last_timestamp, last_due)) if timestamp >= last_timestamp => {
+                let micros = (timestamp - last_timestamp) as f64 * self.factor;
+                last_due + Duration::from_secs_f64(micros / 1e6)
+            }
+            _ => now,
+        };
+        self.last = Some((timestamp, due.max(now)));
+        due.saturating_duration_since(now)
+    }
+}
+
+/// Suppresses consecutive identical messages (`--dedup`)
+struct Dedup {
+    /// Index and rendered message of the last printed frame
+    last: Option<(u64, String)>,
+    repeats: usize,
+    last_report: Instant,
+}
+
+impl Dedup {
+    fn new() -> Self {
+        Self {
+            last: None,
+            repeats: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Returns `true` if `frame` repeats the last message, in which case it should not be printed
+    ///
+    /// Reports the number of suppressed repeats to `sink` when a different message arrives.
+    fn is_repeat(&mut self, frame: &Frame, sink: &mut dyn Write) -> io::Result<bool> {
+        let key = (frame.index(), frame.display_message().to_string());
+        if self.last.as_ref() == Some(&key) {
+            self.repeats += 1;
+            return Ok(true);
+        }
+
+        self.report(sink)?;
+        self.last = Some(key);
+        Ok(false)
+    }
+
+    /// Returns `true` if repeats have been suppressed for a while without being reported
+    fn report_due(&self) -> bool {
+        self.repeats != 0 && self.last_report.elapsed() >= DEDUP_REPORT_INTERVAL
+    }
+
+    /// Reports the number of suppressed repeats, if any, to `sink`
+    fn report(&mut self, sink: &mut dyn Write) -> io::Result<()> {
+        if self.repeats != 0 {
+            writeln!(sink, "(HOST) last message repeated {} times", self.repeats)?;
+            self.repeats = 0;
+        }
+        self.last_report = Instant::now();
+        Ok(())
+    }
+}
+
+/// Caps the number of printed frames per second (`--max-rate`)
+struct Throttle {
+    max_rate: u32,
+    /// Start of the current one-second window
+    window_start: Option<Instant>,
+    /// Frames printed in the current window
+    printed: u32,
+}
+
+impl Throttle {
+    fn new(max_rate: u32) -> Self {
+        Self {
+            max_rate,
+            window_start: None,
+            printed: 0,
+        }
+    }
+
+    /// Returns `true` if a frame received at `now` may be printed
+    fn allow(&mut self, now: Instant) -> bool {
+        let window_start = *self.window_start.get_or_insert(now);
+        if now.duration_since(window_start) >= Duration::from_secs(1) {
+            self.window_start = Some(now);
+            self.printed = 0;
+        }
+
+        if self.printed < self.max_rate {
+            self.printed += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Statistics about a decoding session
+///
+/// Printed on exit and, with `--stats-interval`, periodically.
+#[derive(Default)]
+struct Stats {
+    /// Number of decoded frames, including the ones that were filtered out
+    frames: usize,
+    /// Number of bytes that were discarded because they could not be decoded
+    malformed_bytes: usize,
+    bytes_read: usize,
+    /// Number of frames that were dropped because of `--max-rate`
+    throttled_frames: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "decoded {} frames, skipped {} bytes of malformed data, read {} bytes",
+            self.frames, self.malformed_bytes, self.bytes_read
+        )?;
+        if self.throttled_frames != 0 {
+            write!(
+                f,
+                ", dropped {} frames to stay within `--max-rate`",
+                self.throttled_frames
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes one line per log statement in `elfs` to `output`; used by `--list`.
+fn list(elfs: &[LoadedElf], output: &mut dyn Write) -> anyhow::Result<()> {
+    let current_dir = env::current_dir()?;
+    for elf in elfs {
+        let mut statements = elf
+            .table
+            .indices()
+            .map(|index| (index, None))
+            .collect::<BTreeMap<_, _>>();
+        for loc in elf.locs.iter().flat_map(|locs| locs.values()) {
+            if let Some(statement) = statements.get_mut(&(loc.index as usize)) {
+                *statement = Some(loc);
+            }
+        }
+
+        for (index, loc) in statements {
+            // NOTE(unwrap) `indices` only yields log statements
+            let (level, format) = elf.table.index_to_format(index).unwrap();
+            let location = match loc {
+                Some(loc) => {
+                    // locations recovered from a linker map only know the object file
+                    let file = loc.object.as_ref().unwrap_or(&loc.file);
+                    let file = file.strip_prefix(&current_dir).unwrap_or(file);
+                    match loc.line {
+                        0 => format!("{} {}", file.display(), loc.module),
+                        line => format!("{}:{} {}", file.display(), line, loc.module),
+                    }
+                }
+                None => "<unknown location>".to_string(),
+            };
+            writeln!(
+                output,
+                "{:>5} {:<5} {} {}",
+                index,
+                level.as_str().to_uppercase(),
+                location,
+                format
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a frame with this `timestamp` is to be shown with `--since` and `--until`
+fn in_time_window(timestamp: Option<u128>, opts: &Opts) -> bool {
+    if opts.since.is_none() && opts.until.is_none() {
+        return true;
+    }
+
+    match timestamp {
+        // `Option::is_none_or` needs Rust 1.82
+        #[allow(clippy::unnecessary_map_or)]
+        Some(timestamp) => {
+            opts.since.map_or(true, |since| timestamp >= since)
+                && opts.until.map_or(true, |until| timestamp <= until)
+        }
+        None => !opts.strict_time,
+    }
+}
+
+/// Formats a run of undecodable bytes for `--passthrough-noise`
+fn format_noise(bytes: &[u8]) -> String {
+    let is_text = bytes
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace());
+    if is_text {
+        format!("(NOISE) {}", String::from_utf8_lossy(bytes).trim_end())
+    } else {
+        let hex = bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>();
+        format!("(NOISE) {}", hex.join(" "))
+    }
+}
+
+/// Prints everything read from `reader` as a hex dump, until EOF.
+fn hex_dump(mut reader: impl Read, output: &Output) -> anyhow::Result<()> {
+    let mut buf = [0; READ_BUFFER_SIZE];
+    let mut line = Vec::with_capacity(HEX_DUMP_WIDTH);
+    let mut offset = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for &byte in &buf[..n] {
+            line.push(byte);
+            if line.len() == HEX_DUMP_WIDTH {
+                writeln!(output.lock(), "{}", hex_dump_line(offset, &line))?;
+                offset += line.len();
+                line.clear();
+            }
+        }
+
+        if n == 0 {
+            if !line.is_empty() {
+                writeln!(output.lock(), "{}", hex_dump_line(offset, &line))?;
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Formats up to `HEX_DUMP_WIDTH` bytes like `hexdump -C` does.
+fn hex_dump_line(offset: usize, bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for i in 0..HEX_DUMP_WIDTH {
+        if i == HEX_DUMP_WIDTH / 2 {
+            hex.push(' ');
+        }
+        match bytes.get(i) {
+            Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+            None => hex.push_str("   "),
+        }
+    }
+
+    let ascii = bytes
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+
+    format!("{:08x}  {} |{}|", offset, hex, ascii)
+}
+
+/// Writes `frame` as a single-line JSON object; missing location info is emitted as `null`.
+///
+/// Next to the rendered `message`, `args` holds the typed arguments of the frame (see
+/// `Frame::typed_args`) and `schema` the version of their layout.
+#[cfg(feature = "json")]
+fn print_json(
+    output: &mut dyn Write,
+    frame: &Frame,
+    location: &FrameLocation,
+    host_timestamp: Option<&str>,
+    source: Option<&str>,
+    fields: &[Field],
+) -> anyhow::Result<()> {
+    use defmt_decoder::{TypedArg, TYPED_ARGS_SCHEMA_VERSION};
+
+    #[derive(serde::Serialize)]
+    struct JsonFrame<'a> {
+        message: String,
+        args: Vec<TypedArg<'a>>,
+        level: &'static str,
+        timestamp: Option<String>,
+        file: Option<&'a str>,
+        line: Option<u32>,
+        column: Option<u32>,
+        module_path: Option<&'a str>,
+        crate_name: Option<&'a str>,
+        crate_version: Option<&'a str>,
+    }
+
+    let mut json = serde_json::to_value(&JsonFrame {
+        message: frame.display_message().to_string(),
+        args: frame.typed_args(),
+        level: frame.level().as_str(),
+        timestamp: frame.display_timestamp().map(|ts| ts.to_string()),
+        file: location.file.as_deref(),
+        line: location.line,
+        column: location.column,
+        module_path: location.module_path.as_deref(),
+        crate_name: location.crate_name.as_deref(),
+        crate_version: location.crate_version.as_deref(),
+    })?;
+    if let Some(object) = json.as_object_mut() {
+        if !fields.is_empty() {
+            object.retain(|key, _| {
+                fields
+                    .iter()
+                    .any(|field| field.json_keys().contains(&key.as_str()))
+            });
+        }
+        if let Some(host_timestamp) = host_timestamp {
+            object.insert("host_timestamp".into(), host_timestamp.into());
+        }
+        if let Some(source) = source {
+            object.insert("source".into(), source.into());
+        }
+        object.insert("schema".into(), TYPED_ARGS_SCHEMA_VERSION.into());
+    }
+    writeln!(output, "{}", json)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "json"))]
+fn print_json(
+    _: &mut dyn Write,
+    _: &Frame,
+    _: &FrameLocation,
+    _: Option<&str>,
+    _: Option<&str>,
+    _: &[Field],
+) -> anyhow::Result<()> {
+    unreachable!("`--format json` is rejected when the `json` feature is disabled")
+}
+
+/// Formats the host's local time as ISO-8601 with milliseconds, e.g. for `--host-timestamp`
+fn host_timestamp<Tz: TimeZone>(time: DateTime<Tz>) -> String
+where
+    Tz::Offset: fmt::Display,
+{
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+/// Formats the selected `fields` of a frame as text, separated by spaces; `message` is the frame's
+/// (possibly `--pretty`-reformatted) message.
+///
+/// Fields that are not available (e.g. the file of a frame without location info) are skipped.
+fn format_fields(
+    fields: &[Field],
+    frame: &Frame,
+    message: &str,
+    location: &FrameLocation,
+) -> String {
+    fields
+        .iter()
+        .filter_map(|field| match field {
+            Field::Timestamp => frame.display_timestamp().map(|ts| ts.to_string()),
+            Field::Level => Some(frame.level().as_str().to_uppercase()),
+            Field::File => location.file.clone(),
+            Field::Line => location.line.map(|line| line.to_string()),
+            Field::Column => location.column.map(|column| column.to_string()),
+            Field::Module => location.module_path.clone(),
+            Field::Crate => match (&location.crate_name, &location.crate_version) {
+                (Some(name), Some(version)) => Some(format!("{}@{}", name, version)),
+                (name, _) => name.clone(),
+            },
+            Field::Message => Some(message.to_string()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decodes and prints synthetic frames that cover every log level and a few argument types.
+///
+/// Used by `--self-test`.
+fn self_test() -> anyhow::Result<()> {
+    let table = self_test_table();
+
+    // [index, timestamp, args..]
+    let frames: &[&[u8]] = &[
+        &[0, 1, 42],
+        &[1, 2, 0xd6, 0xff],
+        &[2, 3, 0x00, 0x00, 0xc0, 0x3f, 1],
+        &[3, 4, 5, b'h', b'e', b'l', b'l', b'o'],
+        &[4, 5, 3, 0xde, 0xad, 0xbe],
+    ];
+
+    for bytes in frames {
+        let (frame, consumed) = table.decode(bytes)?;
+        if consumed != bytes.len() {
+            bail!("self-test frame {} was not fully decoded", frame.index());
+        }
+        defmt_decoder::log::log_defmt(&frame, None, None, None, None);
+    }
+
+    host_eprintln!("(HOST) self-test passed: decoded {} frames", frames.len());
+    Ok(())
+}
+
+/// Report version from Cargo.toml _(e.g. "0.1.4")_ and supported `defmt`-versions.
+///
+/// Used by `--version` flag.
+#[allow(clippy::unnecessary_wraps)]
+fn print_version() -> anyhow::Result<()> {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    println!("supported defmt version: {}", defmt_decoder::DEFMT_VERSION);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use defmt_decoder::Location;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    #[test]
+    fn dedup() {
+        let table = self_test_table();
+        let frames = [
+            &[0, 1, 42],
+            &[0, 2, 42],
+            &[0, 3, 42],
+            &[0, 4, 43],
+            &[0, 5, 43],
+        ];
+
+        let mut dedup = Dedup::new();
+        let mut sink = vec![];
+        let mut printed = vec![];
+        for bytes in &frames {
+            let (frame, _) = table.decode(*bytes).unwrap();
+            if !dedup.is_repeat(&frame, &mut sink).unwrap() {
+                printed.push(frame.display_message().to_string());
+            }
+        }
+        dedup.report(&mut sink).unwrap();
+
+        // the timestamps differ but the messages don't
+        assert_eq!(printed, ["trace: 42", "trace: 43"]);
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "(HOST) last message repeated 2 times\n(HOST) last message repeated 1 times\n"
+        );
+    }
+
+    #[test]
+    fn missing_locations() {
+        let table = self_test_table();
+        let mut locs = Locations::new();
+        for index in (0..5).filter(|index| *index != 3) {
+            let loc = Location {
+                index,
+                file: "src/main.rs".into(),
+                line: 1,
+                column: None,
+                module: "app".to_string(),
+                crate_name: None,
+                crate_version: None,
+                object: None,
+            };
+            locs.insert(index, loc);
+        }
+
+        assert_eq!(
+            super::missing_locations(&table, &locs),
+            [(3, "<self-test>")]
+        );
+    }
+
+    #[test]
+    fn table_file_with_partial_locations() {
+        let mut locs = Locations::new();
+        let loc = Location {
+            index: 1,
+            file: "src/main.rs".into(),
+            line: 1,
+            column: None,
+            module: "app".to_string(),
+            crate_name: None,
+            crate_version: None,
+            object: None,
+        };
+        locs.insert(1, loc);
+        let mut file = vec![];
+        defmt_decoder::write_table_file(&mut file, &self_test_table(), Some(&locs)).unwrap();
+        let path = env::temp_dir().join(format!("defmt-print-table-{}.json", process::id()));
+        fs::write(&path, file).unwrap();
+
+        let opts = Opts::from_iter(&["defmt-print".as_ref(), "--table".as_ref(), path.as_os_str()]);
+        let loaded = load(&opts).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // the other log statements have no location, so none are shown
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].locs.is_none());
+    }
+
+    #[test]
+    fn list() {
+        let mut locs = Locations::new();
+        for index in 0..5 {
+            let loc = Location {
+                index,
+                file: "src/main.rs".into(),
+                line: 10 + index,
+                column: None,
+                module: "app".to_string(),
+                crate_name: None,
+                crate_version: None,
+                object: None,
+            };
+            locs.insert(index, loc);
+        }
+        locs.remove(&3);
+        // as recovered from a linker map
+        let loc = locs.get_mut(&2).unwrap();
+        loc.file = PathBuf::new();
+        loc.line = 0;
+        loc.object = Some("/tmp/libapp.rlib(app.o)".into());
+        let elfs = [
+            LoadedElf {
+                table: self_test_table(),
+                locs: Some(locs),
+            },
+            LoadedElf {
+                table: self_test_table(),
+                locs: None,
+            },
+        ];
+
+        let mut output = vec![];
+        super::list(&elfs, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[1], "    1 DEBUG src/main.rs:11 app debug: {=i16}");
+        assert_eq!(
+            lines[2],
+            "    2 INFO  /tmp/libapp.rlib(app.o) app info: {=f32} {=bool}"
+        );
+        assert_eq!(lines[3], "    3 WARN  <unknown location> warn: {=str}");
+        assert_eq!(lines[9], "    4 ERROR <unknown location> error: {=[u8]:x}");
+    }
+
+    #[test]
+    fn fields() {
+        let table = self_test_table();
+        let (frame, _) = table.decode(&[1, 2, 0xd6, 0xff]).unwrap();
+
+        let fields = "level,message,file,timestamp,crate"
+            .split(',')
+            .map(|field| field.parse().unwrap())
+            .collect::<Vec<Field>>();
+        let location = FrameLocation {
+            line: Some(3),
+            module_path: Some("app".to_string()),
+            crate_name: Some("heapless".to_string()),
+            crate_version: Some("0.7.1".to_string()),
+            ..FrameLocation::default()
+        };
+        assert_eq!(
+            format_fields(&fields, &frame, "debug: -42", &location),
+            "DEBUG debug: -42 0.000002 heapless@0.7.1"
+        );
+        assert!("colour".parse::<Field>().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn print_json() {
+        let table = self_test_table();
+        let (frame, _) = table.decode(&[1, 2, 0xd6, 0xff]).unwrap();
+        let location = FrameLocation {
+            line: Some(3),
+            ..FrameLocation::default()
+        };
+
+        let mut output = vec![];
+        super::print_json(&mut output, &frame, &location, None, Some("app"), &[]).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "message": "debug: -42",
+                "args": [{ "type": "i16", "value": -42 }],
+                "schema": defmt_decoder::TYPED_ARGS_SCHEMA_VERSION,
+                "level": "debug",
+                "timestamp": "0.000002",
+                "file": null,
+                "line": 3,
+                "column": null,
+                "module_path": null,
+                "crate_name": null,
+                "crate_version": null,
+                "source": "app",
+            })
+        );
+
+        // `args` goes with `message`; `schema` is always there
+        let fields = [Field::Level, Field::Message];
+        let mut output = vec![];
+        super::print_json(&mut output, &frame, &location, None, None, &fields).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let mut keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["args", "level", "message", "schema"]);
+    }
+
+    #[test]
+    fn host_timestamp() {
+        let time = DateTime::parse_from_rfc3339("2021-04-01T13:37:05.042123+02:00").unwrap();
+        assert_eq!(super::host_timestamp(time), "2021-04-01T13:37:05.042+02:00");
+    }
+
+    #[test]
+    fn mismatch_detector() {
+        let mut detector = MismatchDetector::new(0.5);
+        // not enough data yet
+        assert_eq!(detector.record(0, 600), None);
+        assert_eq!(detector.record(1000, 0), None);
+        // the first pass drops out of the window, leaving 1000 decoded and 500 malformed bytes
+        assert_eq!(detector.record(0, 500), None);
+        // only malformed data left in the window
+        assert_eq!(detector.record(0, 600), Some(1.0));
+        assert!(parse_threshold("1.5").is_err());
+    }
+
+    #[test]
+    fn throttle() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(2);
+        assert!(throttle.allow(start));
+        assert!(throttle.allow(start + Duration::from_millis(100)));
+        assert!(!throttle.allow(start + Duration::from_millis(200)));
+        assert!(!throttle.allow(start + Duration::from_millis(999)));
+        // next window
+        assert!(throttle.allow(start + Duration::from_millis(1000)));
+        assert!(throttle.allow(start + Duration::from_millis(1500)));
+        assert!(!throttle.allow(start + Duration::from_millis(1999)));
+
+        assert_eq!(parse_max_rate("100"), Ok(100));
+        assert!(parse_max_rate("0").is_err());
+    }
+
+    #[test]
+    fn parse_source() {
+        assert_eq!(
+            super::parse_source("radio=serial:/dev/ttyUSB0"),
+            Ok(Source {
+                name: "radio".to_string(),
+                transport: Transport::File("/dev/ttyUSB0".into()),
+            })
+        );
+        assert_eq!(
+            super::parse_source("app=tcp:localhost:8765").map(|source| source.transport),
+            Ok(Transport::Tcp("localhost:8765".to_string()))
+        );
+        assert!(super::parse_source("app=file:").is_err());
+        assert!(super::parse_source("=file:log.bin").is_err());
+        assert!(super::parse_source("app=/dev/ttyUSB0").is_err());
+        assert!(super::parse_source("app").is_err());
+    }
+
+    #[test]
+    fn spawn_readers() {
+        let readers: Vec<Box<dyn Read + Send>> = vec![
+            Box::new(io::Cursor::new(vec![1, 2, 3])),
+            Box::new(io::Cursor::new(vec![4])),
+        ];
+        let mut received = [vec![], vec![]];
+        let mut eofs = 0;
+        for (index, data) in super::spawn_readers(readers) {
+            let data = data.unwrap();
+            if data.is_empty() {
+                eofs += 1;
+            }
+            received[index].extend(data);
+        }
+        assert_eq!(received, [vec![1, 2, 3], vec![4]]);
+        assert_eq!(eofs, 2);
+    }
+
+    #[test]
+    fn ready_byte() {
+        assert_eq!(parse_ready_byte("63"), Ok(b'c'));
+        assert_eq!(parse_ready_byte("0xff"), Ok(0xff));
+        assert!(parse_ready_byte("100").is_err());
+        assert!(parse_ready_byte("").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "usb")]
+    fn usb_id() {
+        assert_eq!(parse_usb_id("1209:0001"), Ok((0x1209, 0x0001)));
+        assert_eq!(parse_usb_id("0xc0de:0xCAFE"), Ok((0xc0de, 0xcafe)));
+        assert!(parse_usb_id("1209").is_err());
+        assert!(parse_usb_id("12345:1").is_err());
+    }
+
+    #[test]
+    fn decompress() {
+        let elf = b"\x7fELF\x01\x01\x01";
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(elf).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(*super::decompress(&compressed).unwrap(), elf[..]);
+        assert!(matches!(super::decompress(elf).unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn follow_fifo_reopens_at_eof() {
+        let path = env::temp_dir().join(format!("defmt-print-follow-{}", process::id()));
+        fs::write(&path, b"ab").unwrap();
+
+        let mut reader = FollowFifo {
+            file: fs::File::open(&path).unwrap(),
+            path: path.clone(),
+        };
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        // a regular file stands in for the pipe; at EOF it's reopened and read from the start
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn in_time_window() {
+        let opts = Opts::from_iter(&[
+            "defmt-print",
+            "--self-test",
+            "--since",
+            "10",
+            "--until",
+            "20",
+        ]);
+        assert!(!super::in_time_window(Some(9), &opts));
+        assert!(super::in_time_window(Some(10), &opts));
+        assert!(super::in_time_window(Some(20), &opts));
+        assert!(!super::in_time_window(Some(21), &opts));
+        assert!(super::in_time_window(None, &opts));
+
+        let opts = Opts::from_iter(&[
+            "defmt-print",
+            "--self-test",
+            "--since",
+            "10",
+            "--strict-time",
+        ]);
+        assert!(super::in_time_window(Some(1000), &opts));
+        assert!(!super::in_time_window(None, &opts));
+
+        let opts = Opts::from_iter(&["defmt-print", "--self-test", "--strict-time"]);
+        assert!(super::in_time_window(None, &opts));
+    }
+
+    #[test]
+    fn pacer() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(0.5);
+        assert_eq!(pacer.delay(1_000, start), Duration::ZERO);
+        // 2ms of device time at twice the speed
+        assert_eq!(pacer.delay(3_000, start), Duration::from_millis(1));
+        // printing took longer than the delay; don't make up for it later
+        let late = start + Duration::from_millis(10);
+        assert_eq!(pacer.delay(5_000, late), Duration::ZERO);
+        assert_eq!(pacer.delay(7_000, late), Duration::from_millis(1));
+        // the device was reset
+        assert_eq!(pacer.delay(0, late), Duration::ZERO);
+    }
+
+    #[test]
+    fn check_stdin_use() {
+        let check = |args: &[&str]| {
+            let opts = Opts::from_iter(["defmt-print"].iter().chain(args));
+            super::check_stdin_use(&opts).is_ok()
+        };
+
+        assert!(check(&["--elf", "app.elf"]));
+        assert!(check(&["--elf", "-", "--file", "log.bin"]));
+        assert!(check(&["--elf", "-", "--list"]));
+        assert!(!check(&["--elf", "-"]));
+        assert!(!check(&["--elf", "-", "--elf", "-", "--file", "log.bin"]));
+        assert!(!check(&["--elf", "-", "--file", "log.bin", "--watch-elf"]));
+    }
+
+    #[test]
+    fn format_noise() {
+        assert_eq!(super::format_noise(b"MODE 2\r\n"), "(NOISE) MODE 2");
+        assert_eq!(super::format_noise(&[0x7f, 0x7e, 0]), "(NOISE) 7f 7e 00");
+    }
+
+    #[test]
+    fn hex_dump_line() {
+        assert_eq!(
+            super::hex_dump_line(0x10, b"defmt\x00\x01\xffHello, w"),
+            "00000010  64 65 66 6d 74 00 01 ff  48 65 6c 6c 6f 2c 20 77  |defmt...Hello, w|"
+        );
+        assert_eq!(
+            super::hex_dump_line(0x20, b"orld"),
+            "00000020  6f 72 6c 64                                       |orld|"
+        );
+    }
 }
@@ -0,0 +1,219 @@
+//! Multi-line reflow of long messages for `--pretty`
+
+/// Messages up to this many characters are left on a single line
+pub const PRETTY_WIDTH: usize = 80;
+
+const INDENT: usize = 4;
+
+/// A piece of a message: plain text or a bracketed, comma-separated group
+enum Node<'a> {
+    Text(&'a str),
+    Group {
+        open: char,
+        close: char,
+        /// The group as it appears in the message
+        flat: &'a str,
+        items: Vec<Vec<Node<'a>>>,
+    },
+}
+
+/// Reflows `message` across multiple lines, similar to `{:#?}`, if it's longer than `width`.
+///
+/// Bracketed groups (`{ .. }`, `[ .. ]`, `( .. )`) that don't fit on the current line get one
+/// indented line per comma-separated item; brackets inside string literals are ignored. Messages
+/// that are short enough, already span multiple lines (like `assert_eq!` failures) or whose
+/// brackets don't balance are returned unchanged.
+pub fn pretty_message(message: &str, width: usize) -> String {
+    if message.chars().count() <= width || message.contains('\n') {
+        return message.to_string();
+    }
+
+    let mut parser = Parser {
+        message,
+        position: 0,
+    };
+    match parser.nodes(None) {
+        Some(nodes) if parser.position == message.len() => {
+            let mut output = String::new();
+            render(&nodes, 0, width, &mut output);
+            output
+        }
+        _ => message.to_string(),
+    }
+}
+
+struct Parser<'a> {
+    message: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Parses nodes up to (but excluding) `close`, or to the end of the message if `close` is
+    /// `None`. Returns `None` on unbalanced brackets.
+    fn nodes(&mut self, close: Option<char>) -> Option<Vec<Node<'a>>> {
+        let mut nodes = vec![];
+        let mut text_start = self.position;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        while let Some(c) = self.message[self.position..].chars().next() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                self.position += c.len_utf8();
+                continue;
+            }
+
+            match c {
+                '"' => in_string = true,
+                '{' | '[' | '(' => {
+                    push_text(&mut nodes, &self.message[text_start..self.position]);
+                    nodes.push(self.group(c)?);
+                    text_start = self.position;
+                    continue;
+                }
+                '}' | ']' | ')' | ',' if close.is_some() => break,
+                '}' | ']' | ')' => return None,
+                _ => {}
+            }
+            self.position += c.len_utf8();
+        }
+
+        if close.is_some() && self.position == self.message.len() {
+            return None;
+        }
+        push_text(&mut nodes, &self.message[text_start..self.position]);
+        Some(nodes)
+    }
+
+    /// Parses the group that starts with the `open` bracket at the current position
+    fn group(&mut self, open: char) -> Option<Node<'a>> {
+        let close = match open {
+            '{' => '}',
+            '[' => ']',
+            _ => ')',
+        };
+        let start = self.position;
+        self.position += open.len_utf8();
+
+        let mut items = vec![];
+        loop {
+            let item = self.nodes(Some(close))?;
+            items.push(item);
+            match self.message[self.position..].chars().next()? {
+                ',' => self.position += 1,
+                c if c == close => {
+                    self.position += c.len_utf8();
+                    break;
+                }
+                // mismatched bracket
+                _ => return None,
+            }
+        }
+
+        Some(Node::Group {
+            open,
+            close,
+            flat: &self.message[start..self.position],
+            items,
+        })
+    }
+}
+
+fn push_text<'a>(nodes: &mut Vec<Node<'a>>, text: &'a str) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+}
+
+fn render(nodes: &[Node], indent: usize, width: usize, output: &mut String) {
+    for node in nodes {
+        render_node(node, indent, width, output);
+    }
+}
+
+fn render_node(node: &Node, indent: usize, width: usize, output: &mut String) {
+    let (open, close, flat, items) = match node {
+        Node::Text(text) => return output.push_str(text),
+        Node::Group {
+            open,
+            close,
+            flat,
+            items,
+        } => (open, close, flat, items),
+    };
+
+    let line_start = output.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = output[line_start..].chars().count();
+    let items = items
+        .iter()
+        .filter(|item| !is_blank(item))
+        .collect::<Vec<_>>();
+    if column + flat.chars().count() <= width || items.is_empty() {
+        output.push_str(flat);
+        return;
+    }
+
+    output.push(*open);
+    for item in items {
+        output.push('\n');
+        push_indent(output, indent + INDENT);
+        // drop the whitespace around the item (`{ x: 1 }` has a space after `{`)
+        let last = item.len() - 1;
+        for (i, node) in item.iter().enumerate() {
+            match node {
+                Node::Text(text) if i == 0 && i == last => output.push_str(text.trim()),
+                Node::Text(text) if i == 0 => output.push_str(text.trim_start()),
+                Node::Text(text) if i == last => output.push_str(text.trim_end()),
+                node => render_node(node, indent + INDENT, width, output),
+            }
+        }
+        output.push(',');
+    }
+    output.push('\n');
+    push_indent(output, indent);
+    output.push(*close);
+}
+
+fn is_blank(item: &[Node]) -> bool {
+    item.iter()
+        .all(|node| matches!(node, Node::Text(text) if text.trim().is_empty()))
+}
+
+fn push_indent(output: &mut String, indent: usize) {
+    output.push_str(&" ".repeat(indent));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_messages_are_unchanged() {
+        let message = "Config { id: 1, name: \"radio\" }";
+        assert_eq!(pretty_message(message, PRETTY_WIDTH), message);
+    }
+
+    #[test]
+    fn reflow() {
+        let message = "state: Config { id: 1, name: \"a, {b\", pins: [1, 2, 3] }, ok";
+        assert_eq!(
+            pretty_message(message, 30),
+            "state: Config {\n    id: 1,\n    name: \"a, {b\",\n    pins: [1, 2, 3],\n}, ok"
+        );
+        assert_eq!(
+            pretty_message(message, 15),
+            "state: Config {\n    id: 1,\n    name: \"a, {b\",\n    pins: [\n        1,\n        2,\n        3,\n    ],\n}, ok"
+        );
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        let message = "value out of range (0..10]: 11 and then some more text that is long";
+        assert_eq!(pretty_message(message, 20), message);
+    }
+}
@@ -0,0 +1,97 @@
+//! Capture files written by `--record` and read back by `--replay`
+//!
+//! A capture is a small header followed by the raw defmt bytes, exactly as they were received.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{bail, ensure};
+
+const MAGIC: &[u8; 8] = b"DEFMTREC";
+const FORMAT_VERSION: u8 = 1;
+
+/// What a capture file says about itself
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    /// Hash of the ELF file(s) the capture was recorded with; `0` if it's unknown
+    pub elf_hash: u64,
+    /// When the recording started, in milliseconds since the Unix epoch
+    pub recorded_at: i64,
+}
+
+impl Header {
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&self.elf_hash.to_le_bytes())?;
+        writer.write_all(&self.recorded_at.to_le_bytes())
+    }
+
+    pub fn read(mut reader: impl Read) -> anyhow::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a `--record` capture file");
+
+        let mut version = [0];
+        reader.read_exact(&mut version)?;
+        if version[0] != FORMAT_VERSION {
+            bail!("unsupported capture file version {}", version[0]);
+        }
+
+        let mut elf_hash = [0; 8];
+        reader.read_exact(&mut elf_hash)?;
+        let mut recorded_at = [0; 8];
+        reader.read_exact(&mut recorded_at)?;
+        Ok(Self {
+            elf_hash: u64::from_le_bytes(elf_hash),
+            recorded_at: i64::from_le_bytes(recorded_at),
+        })
+    }
+}
+
+/// Copies everything that is read from `R` into the capture file `W` (`--record`)
+pub struct Recorder<R, W> {
+    pub reader: R,
+    pub capture: W,
+}
+
+impl<R: Read, W: Write> Read for Recorder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.capture.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let header = Header {
+            elf_hash: 0x0123_4567_89ab_cdef,
+            recorded_at: 1_617_277_025_042,
+        };
+        let mut capture = vec![];
+        header.write(&mut capture).unwrap();
+        capture.extend_from_slice(&[1, 2, 3]);
+
+        let mut reader = &capture[..];
+        assert_eq!(Header::read(&mut reader).unwrap(), header);
+        assert_eq!(reader, [1, 2, 3]);
+
+        assert!(Header::read(&b"\x01\x02\x03 raw defmt data"[..]).is_err());
+    }
+
+    #[test]
+    fn recorder() {
+        let mut recorder = Recorder {
+            reader: &[1, 2, 3, 4][..],
+            capture: vec![0xff],
+        };
+        let mut received = vec![];
+        recorder.read_to_end(&mut received).unwrap();
+        assert_eq!(received, [1, 2, 3, 4]);
+        assert_eq!(recorder.capture, [0xff, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Reading defmt data from a USB bulk endpoint (`--usb`)
+
+use std::{
+    io::{self, Read},
+    thread,
+    time::Duration,
+};
+
+use defmt_print::host_eprintln;
+use rusb::{Direction, GlobalContext, TransferType};
+
+/// How long a single bulk read waits for data before giving control back to the decode loop
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Delay between attempts to open a device that is not (or no longer) connected
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Reads from the first bulk IN endpoint of a USB device
+///
+/// If the device is unplugged, the reader waits for it to come back and resumes reading. While
+/// no data arrives, `read` fails with `ErrorKind::TimedOut` so that the caller can check for
+/// Ctrl-C.
+pub struct UsbReader {
+    vid: u16,
+    pid: u16,
+    ready_byte: Option<u8>,
+    device: Option<Device>,
+}
+
+struct Device {
+    handle: rusb::DeviceHandle<GlobalContext>,
+    in_endpoint: u8,
+}
+
+impl UsbReader {
+    /// Opens the device with the given vendor and product ID, waiting for it to be connected.
+    ///
+    /// `ready_byte` is written to the bulk OUT endpoint (if the interface has one) every time the
+    /// device is (re)opened.
+    pub fn open(vid: u16, pid: u16, ready_byte: Option<u8>) -> anyhow::Result<Self> {
+        let mut reader = Self {
+            vid,
+            pid,
+            ready_byte,
+            device: None,
+        };
+
+        let mut waiting = false;
+        while reader.device.is_none() {
+            reader.device = reader.try_open()?;
+            if reader.device.is_none() {
+                if !waiting {
+                    host_eprintln!("(HOST) waiting for USB device {:04x}:{:04x}", vid, pid);
+                    waiting = true;
+                }
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+
+        Ok(reader)
+    }
+
+    /// Opens the device and claims the interface with the bulk IN endpoint.
+    ///
+    /// Returns `None` if the device is not connected.
+    fn try_open(&self) -> anyhow::Result<Option<Device>> {
+        let handle = match rusb::open_device_with_vid_pid(self.vid, self.pid) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+
+        let config = handle.device().active_config_descriptor()?;
+        for interface in config.interfaces() {
+            for descriptor in interface.descriptors() {
+                let bulk_endpoint = |direction| {
+                    descriptor
+                        .endpoint_descriptors()
+                        .find(|endpoint| {
+                            endpoint.transfer_type() == TransferType::Bulk
+                                && endpoint.direction() == direction
+                        })
+                        .map(|endpoint| endpoint.address())
+                };
+
+                let in_endpoint = match bulk_endpoint(Direction::In) {
+                    Some(address) => address,
+                    None => continue,
+                };
+
+                // not supported on all platforms; claiming the interface fails if it's needed
+                handle.set_auto_detach_kernel_driver(true).ok();
+                handle.claim_interface(descriptor.interface_number())?;
+
+                if let (Some(byte), Some(out_endpoint)) =
+                    (self.ready_byte, bulk_endpoint(Direction::Out))
+                {
+                    // let the device know that we are ready to receive data
+                    handle.write_bulk(out_endpoint, &[byte], WRITE_TIMEOUT)?;
+                }
+
+                return Ok(Some(Device {
+                    handle,
+                    in_endpoint,
+                }));
+            }
+        }
+
+        anyhow::bail!(
+            "USB device {:04x}:{:04x} has no bulk IN endpoint",
+            self.vid,
+            self.pid
+        )
+    }
+}
+
+impl Read for UsbReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let device = match &self.device {
+            Some(device) => device,
+            None => {
+                self.device = self.try_open().map_err(to_io_error)?;
+                if self.device.is_some() {
+                    host_eprintln!("(HOST) USB device reconnected");
+                } else {
+                    thread::sleep(RECONNECT_DELAY);
+                }
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        };
+
+        match device
+            .handle
+            .read_bulk(device.in_endpoint, buf, READ_TIMEOUT)
+        {
+            Ok(n) => Ok(n),
+            Err(rusb::Error::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+            Err(rusb::Error::NoDevice) | Err(rusb::Error::Io) | Err(rusb::Error::Pipe) => {
+                host_eprintln!("(HOST) USB device disconnected; waiting for it to come back");
+                self.device = None;
+                Err(io::ErrorKind::TimedOut.into())
+            }
+            Err(e) => Err(to_io_error(e)),
+        }
+    }
+}
+
+fn to_io_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::other(e)
+}
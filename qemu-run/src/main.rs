@@ -10,7 +10,7 @@ use std::{
 };
 
 use anyhow::{anyhow, bail};
-use defmt_decoder::{DecodeError, Table};
+use defmt_decoder::{DecodeError, FrameStreamDecoder, Table};
 use process::Child;
 
 fn main() -> Result<(), anyhow::Error> {
@@ -64,27 +64,29 @@ fn notmain() -> Result<Option<i32>, anyhow::Error> {
         .take()
         .ok_or_else(|| anyhow!("failed to acquire child's stdout handle"))?;
 
-    let mut frames = vec![];
+    let mut decoder = table.new_stream_decoder();
     let mut readbuf = [0; 256];
     let exit_code;
     loop {
         let n = stdout.read(&mut readbuf)?;
 
         if n != 0 {
-            frames.extend_from_slice(&readbuf[..n]);
+            decoder.push(&readbuf[..n]);
 
-            decode(&mut frames, &table)?;
+            decode(&mut decoder)?;
         }
 
         if let Some(status) = child.0.try_wait()? {
             exit_code = status.code();
 
-            stdout.read_to_end(&mut frames)?;
-            decode(&mut frames, &table)?;
-            if !frames.is_empty() {
+            let mut rest = vec![];
+            stdout.read_to_end(&mut rest)?;
+            decoder.push(&rest);
+            decode(&mut decoder)?;
+            if !decoder.buffered().is_empty() {
                 return Err(anyhow!(
                     "couldn't decode all data (remaining: {:x?})",
-                    frames
+                    decoder.buffered()
                 ));
             }
 
@@ -95,19 +97,16 @@ fn notmain() -> Result<Option<i32>, anyhow::Error> {
     Ok(exit_code)
 }
 
-fn decode(frames: &mut Vec<u8>, table: &Table) -> Result<(), DecodeError> {
+fn decode(decoder: &mut FrameStreamDecoder) -> Result<(), DecodeError> {
     loop {
-        match table.decode(&frames) {
-            Ok((frame, consumed)) => {
-                println!("{}", frame.display(true));
-                let n = frames.len();
-                frames.rotate_left(consumed);
-                frames.truncate(n - consumed);
-            }
-            Err(DecodeError::UnexpectedEof) => return Ok(()),
-            Err(DecodeError::Malformed) => {
-                eprintln!("failed to decode defmt data: {:x?}", frames);
-                return Err(DecodeError::Malformed);
+        // check for malformed data before `decode` skips over it, so it can be reported
+        let buffered = decoder.buffered().to_vec();
+        match decoder.decode() {
+            Some(Ok(frame)) => println!("{}", frame.display(true)),
+            None => return Ok(()),
+            Some(Err(e)) => {
+                eprintln!("failed to decode defmt data: {:x?}", buffered);
+                return Err(e);
             }
         }
     }